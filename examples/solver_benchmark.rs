@@ -0,0 +1,31 @@
+use std::time;
+use minesweeper::ai::build_solver;
+use minesweeper::ai::SolverBackend;
+use minesweeper::board::Board;
+use minesweeper::board::Difficulty;
+use minesweeper::board::Point;
+
+// Times repeated solves of the frontier after an opening click, for each backend
+fn main() {
+    let boards = [Difficulty::Beginner, Difficulty::Intermediate, Difficulty::Expert,
+                  Difficulty::Custom{width: 50, height: 50, mine_count: 500}];
+    let backends = [SolverBackend::Backtracking, SolverBackend::Sat];
+    let trials = 10;
+    let solves = 50;
+    for difficulty in boards.iter() {
+        for backend in backends.iter() {
+            let mut elapsed = time::Duration::from_millis(0);
+            for _ in 0..trials {
+                let mut board = Board::new_from_difficulty(*difficulty).expect("no board!");
+                board.probe(&Point(difficulty.height() / 2, difficulty.width() / 2, 0));
+                let mut solver = build_solver(&board, *backend);
+                let start = time::Instant::now();
+                for _ in 0..solves {
+                    solver.solve().expect("failed to find a solution");
+                }
+                elapsed += start.elapsed();
+            }
+            println!("{} {:?}: {:?} per solve", difficulty, backend, elapsed / (trials * solves));
+        }
+    }
+}
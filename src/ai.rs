@@ -1,78 +1,107 @@
-use std::sync::{Mutex, Arc};
+use std::sync::Arc;
 use super::board::Board;
 use super::board::Point;
+use super::board::ClueNoise;
+use super::render::Renderer;
+use super::hint;
+use super::hint::Hint;
+use super::hint::Reason;
 use super::ActionType;
 use super::Agent;
 use super::constraint::Variable;
 use super::constraint::Constraint;
 use super::constraint::ConstraintSolver;
 use super::constraint::RandomSelectionStrategy;
+use super::constraint::Solver;
+use super::constraint::ParallelSearch;
+use super::constraint::SolutionCounts;
+use super::sat::CardinalitySolver;
+use super::dimacs::MineEncoding;
+use super::clock::Instant;
 use std::thread;
 use std::time;
 use std::collections::HashSet;
 use std::collections::HashMap;
 use rand::seq::SliceRandom;
 
-struct MineConstraint {
-    expected_mines: i32,
-    expected_empties: i32,
-    constrained_points: Vec<Point>,
-    global: bool //FIXME: this is lazy of me
+// The cells in constrained_points hold between min_mines and max_mines mines
+// between them, each cell holding 0 to mines_per_cell. With noisy clues the
+// range covers every count the clue could be hiding and solutions are weighted
+// by how likely the clue is given their count
+pub(crate) struct MineConstraint {
+    pub(crate) min_mines: i32,
+    pub(crate) max_mines: i32,
+    pub(crate) mines_per_cell: i32,
+    pub(crate) constrained_points: Vec<Point>,
+    pub(crate) global: bool, //FIXME: this is lazy of me
+    clue: usize,
+    known_mines: usize,
+    noise: ClueNoise
 }
 
-impl Constraint<Point, bool> for MineConstraint {
+impl Constraint<Point, usize> for MineConstraint {
     fn get_constrained_variable_ids(&self) -> Vec<Point>{
-        self.constrained_points.iter().copied().collect()
+        self.constrained_points.to_vec()
     }
 
-    fn check_constraint(&self, global_counts: &HashMap<bool, usize>,
-                        variable_lookup: &HashMap<Point, Variable<Point,bool>>)-> bool {
-        let (mined, empty) = if self.global {
-            (*global_counts.get(&true).unwrap() as i32, *global_counts.get(&false).unwrap() as i32)
-        } else {
-            self.count_remaining_mined_and_empty(&variable_lookup)
-        };
-        mined <= self.expected_mines && empty <= self.expected_empties
+    fn check_constraint(&self, global_counts: &HashMap<usize, usize>,
+                        variable_lookup: &HashMap<Point, Variable<Point, usize>>)-> bool {
+        if self.global {
+            // only the upper bound, the counts don't say how many cells are left
+            let mined: usize = global_counts.iter().map(|(mines, count)| mines * count).sum();
+            return mined as i32 <= self.max_mines
+        }
+        let (mined, unassigned) = self.count_mined_and_unassigned(variable_lookup, None);
+        self.allows(mined, unassigned)
     }
 
-    fn consistent_states_for_variable(&self, variable_lookup: &HashMap<Point, Variable<Point, bool>>, _: &Point) -> Vec<bool>{
-        let (mined, empty) = self.count_remaining_mined_and_empty(variable_lookup);
-        let mut possible = Vec::with_capacity(2);
-        if mined > 0 {
-            possible.push(true);
-        };
-        if empty > 0 {
-            possible.push(false);
+    fn consistent_states_for_variable(&self, variable_lookup: &HashMap<Point, Variable<Point, usize>>, v_id: &Point) -> Vec<usize>{
+        let (mined, unassigned) = self.count_mined_and_unassigned(variable_lookup, Some(v_id));
+        (0..self.mines_per_cell + 1)
+            .filter(|mines| self.allows(mined + mines, unassigned))
+            .map(|mines| mines as usize)
+            .collect()
+    }
+
+    fn weight(&self, solution: &HashMap<Point, usize>) -> f64 {
+        if self.global {
+            return 1.0
         }
-        possible
+        let mines: usize = self.constrained_points.iter()
+            .filter_map(|point| solution.get(point))
+            .sum();
+        self.noise.likelihood(self.clue, mines + self.known_mines)
     }
 }
 
 impl MineConstraint {
 
-    fn count_remaining_mined_and_empty(&self, variable_lookup: &HashMap<Point, Variable<Point, bool>>) -> (i32, i32) {
+    // mines in the assigned cells and the number of unassigned cells, leaving out `skip`
+    fn count_mined_and_unassigned(&self, variable_lookup: &HashMap<Point, Variable<Point, usize>>, skip: Option<&Point>) -> (i32, i32) {
         self.constrained_points.iter()
+            .filter(|v_id| Some(*v_id) != skip)
             .map(|v_id| variable_lookup.get(v_id).expect("variable not in lookup"))
             .map(|variable| {
                 match variable.value {
-                    None => (0, 0),
-                    Some(val) => {
-                        if val {
-                            (1, 0)
-                        } else {
-                            (0, 1)
-                        }
-                    }
+                    None => (0, 1),
+                    Some(mines) => (mines as i32, 0)
                 }
             })
             .fold((0, 0), |acc, next| (acc.0 + next.0, acc.1 + next.1))
     }
+
+    fn allows(&self, mined: i32, unassigned: i32) -> bool {
+        mined <= self.max_mines && mined + unassigned * self.mines_per_cell >= self.min_mines
+    }
 }
 
 fn construct_global_constraint(board: &Board) -> MineConstraint{
-    let expected_mines = board.remaining_mines(); //this is really just a number we can't exceed
-    let expected_empties = board.unknown_count() as i32 - board.remaining_mines();
-    MineConstraint{expected_mines, expected_empties, constrained_points: vec![], global: true}
+    let mines_per_cell = board.mines_per_cell() as i32;
+    let max_mines = board.remaining_mines(); //this is really just a number we can't exceed
+    let off_border = board.unknown_count() as i32 - board.get_border_points().len() as i32;
+    let min_mines = max_mines - off_border * mines_per_cell;
+    MineConstraint{min_mines, max_mines, mines_per_cell, constrained_points: vec![], global: true,
+                   clue: 0, known_mines: 0, noise: ClueNoise::Exact}
 }
 
 
@@ -83,14 +112,19 @@ fn construct_constraint(board: &Board, point: &Point) -> MineConstraint
         .filter(|cell| cell.knowledge.is_unknown())
         .map(|cell| cell.point)
         .collect();
-    let total_unknown = unknown_neighbors.len();
     let known_mines = board.count_assumed_mined_neighbors(point);
-    let expected_mines = cell.mined_neighbor_count as i32 - known_mines as i32;
-    let expected_empties = total_unknown as i32 - expected_mines as i32 ;
-    MineConstraint{expected_mines, expected_empties, constrained_points:unknown_neighbors, global: false}
+    let expected_mines = cell.clue as i32 - known_mines as i32;
+    let mines_per_cell = board.mines_per_cell() as i32;
+    let noise = board.clue_noise();
+    let slack = match noise {
+        ClueNoise::Exact => 0,
+        ClueNoise::OffByOne(_) => 1
+    };
+    MineConstraint{min_mines: expected_mines - slack, max_mines: expected_mines + slack, mines_per_cell,
+                   constrained_points: unknown_neighbors, global: false, clue: cell.clue, known_mines, noise}
 }
 
-fn build_constraint_solver(board: &Board) -> ConstraintSolver<Point, bool, RandomSelectionStrategy>
+pub(crate) fn build_mine_constraints(board: &Board) -> Vec<MineConstraint>
 {
     let points: Vec<Point> = board.get_border_points(); //Not great i call this multiple times each search
 
@@ -98,95 +132,495 @@ fn build_constraint_solver(board: &Board) -> ConstraintSolver<Point, bool, Rando
         .flat_map(|point| board.neighbor_points(point))
         .collect();
 
-    let mut constraints: Vec<Arc<dyn Constraint<Point, bool>  + Send + Sync>> = constraining_points.iter()
-        .map(|point| board.retrieve_cell(&point))
+    let mut constraints: Vec<MineConstraint> = constraining_points.iter()
+        .map(|point| board.retrieve_cell(point))
         .filter(|cell| cell.is_known_unmined() && board.has_unknown_neighbors(&cell.point))
-        .map(|cell| {
-            let constraint = construct_constraint(&board, &cell.point);
-            let r: Arc<dyn Constraint<Point, bool>  + Send + Sync> = Arc::new(constraint);
+        .map(|cell| construct_constraint(board, &cell.point))
+        .collect();
+
+    constraints.push(construct_global_constraint(board));
+    constraints
+}
+
+// every cell can hold anything from no mines up to the board's limit
+fn frontier_variable(board: &Board, point: Point) -> Variable<Point, usize> {
+    Variable{id: point, value: None, possible: (0..board.mines_per_cell() + 1).collect()}
+}
+
+fn build_constraint_solver(board: &Board) -> ConstraintSolver<Point, usize, RandomSelectionStrategy>
+{
+    let constraints = build_mine_constraints(board).into_iter()
+        .map(|constraint| {
+            let r: Arc<dyn Constraint<Point, usize>  + Send + Sync> = Arc::new(constraint);
             r
         })
         .collect();
 
-    constraints.push(Arc::new(construct_global_constraint(&board)));
-
-    let variables = points.into_iter()
-        .map(|point| Variable{id: point, value: None, possible: vec![false, true]})
+    let variables = board.get_border_points().into_iter()
+        .map(|point| frontier_variable(board, point))
         .collect();
     ConstraintSolver::new(variables, constraints, RandomSelectionStrategy{})
 }
 
+// Cells with several mines are encoded in unary: (point, j) is true when
+// the point holds at least j mines
+struct MineSatSolver {
+    sat: CardinalitySolver<(Point, usize)>,
+    points: Vec<Point>,
+    mines_per_cell: usize,
+    constraints: Vec<MineConstraint>
+}
+
+impl Solver<Point, usize> for MineSatSolver {
+    fn solve(&mut self) -> Option<HashMap<Point, usize>> {
+        let model = self.sat.solve()?;
+        Some(self.points.iter()
+            .map(|point| (*point, (1..self.mines_per_cell + 1).filter(|j| model[&(*point, *j)]).count()))
+            .collect())
+    }
+
+    fn weight(&self, solution: &HashMap<Point, usize>) -> f64 {
+        self.constraints.iter().map(|constraint| constraint.weight(solution)).product()
+    }
+}
+
+fn unary_literals(points: &[Point], mines_per_cell: usize) -> Vec<(Point, usize)> {
+    points.iter()
+        .flat_map(|point| (1..mines_per_cell + 1).map(move |j| (*point, j)))
+        .collect()
+}
+
+fn build_sat_solver(board: &Board) -> MineSatSolver
+{
+    let points = board.get_border_points();
+    let mines_per_cell = board.mines_per_cell();
+    let mut sat = CardinalitySolver::new(unary_literals(&points, mines_per_cell));
+    for point in &points {
+        for j in 1..mines_per_cell {
+            sat.add_clause(&[((*point, j), true), ((*point, j + 1), false)]);
+        }
+    }
+    let constraints = build_mine_constraints(board);
+    for constraint in &constraints {
+        let constrained = if constraint.global { &points } else { &constraint.constrained_points };
+        let literals = unary_literals(constrained, mines_per_cell);
+        sat.add_at_most(&literals, true, constraint.max_mines);
+        sat.add_at_most(&literals, false, literals.len() as i32 - constraint.min_mines);
+    }
+    MineSatSolver{sat, points, mines_per_cell, constraints}
+}
+
+// A backtracking solver over the frontier that follows the board's knowledge
+// changes, so only the neighbourhood of what changed is rebuilt each move
+pub struct IncrementalSolver {
+    solver: ConstraintSolver<Point, usize, RandomSelectionStrategy>,
+    constraints: HashMap<Point, Arc<dyn Constraint<Point, usize>  + Send + Sync>>,
+    seen_changes: usize
+}
+
+impl IncrementalSolver {
+    pub fn new(board: &Board) -> IncrementalSolver {
+        // the global constraint constrains no variables directly, so ConstraintSolver
+        // never checks it and it is left out here
+        let solver = ConstraintSolver::new(vec![], vec![], RandomSelectionStrategy{});
+        let seen_changes = board.knowledge_change_count();
+        let mut incremental = IncrementalSolver{solver, constraints: HashMap::new(), seen_changes};
+        incremental.refresh(board, &board.size.points());
+        incremental
+    }
+
+    pub fn update(&mut self, board: &Board) {
+        if self.seen_changes > board.knowledge_change_count() {
+            // not the board we were built from
+            *self = IncrementalSolver::new(board);
+            return
+        }
+        let affected: HashSet<Point> = board.knowledge_changes_since(self.seen_changes).iter()
+            .flat_map(|point| board.neighbor_points(point).into_iter().chain(Some(*point)))
+            .collect();
+        self.seen_changes = board.knowledge_change_count();
+        let affected: Vec<Point> = affected.into_iter().collect();
+        self.refresh(board, &affected);
+    }
+
+    pub fn variables(&self) -> Vec<Point> {
+        self.solver.variable_lookup.keys().copied().collect()
+    }
+
+    pub fn constraint_sources(&self) -> Vec<Point> {
+        self.constraints.keys().copied().collect()
+    }
+
+    fn refresh(&mut self, board: &Board, points: &[Point]) {
+        for point in points {
+            if let Some(constraint) = self.constraints.remove(point) {
+                self.solver.remove_constraint(&constraint);
+            }
+        }
+        for point in points {
+            let is_border = board.retrieve_cell(point).knowledge.is_unknown() && board.has_known_neighbors(point);
+            if !is_border {
+                self.solver.remove_variable(point);
+            } else if !self.solver.variable_lookup.contains_key(point) {
+                self.solver.add_variable(frontier_variable(board, *point));
+            }
+        }
+        for point in points {
+            if board.retrieve_cell(point).is_known_unmined() && board.has_unknown_neighbors(point) {
+                let constraint: Arc<dyn Constraint<Point, usize>  + Send + Sync> = Arc::new(construct_constraint(board, point));
+                self.solver.add_constraint(Arc::clone(&constraint));
+                self.constraints.insert(*point, constraint);
+            }
+        }
+    }
+}
+
+impl Solver<Point, usize> for IncrementalSolver {
+    fn solve(&mut self) -> Option<HashMap<Point, usize>> {
+        self.solver.backtrack()
+    }
+
+    fn weight(&self, solution: &HashMap<Point, usize>) -> f64 {
+        self.solver.weight(solution)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SolverBackend {
+    Backtracking,
+    Sat
+}
+
+pub fn build_solver(board: &Board, backend: SolverBackend) -> Box<dyn Solver<Point, usize> + Send>
+{
+    match backend {
+        SolverBackend::Backtracking => Box::new(build_constraint_solver(board)),
+        SolverBackend::Sat => Box::new(build_sat_solver(board))
+    }
+}
+
+// The solver a NaiveAI thread keeps between moves
+enum BackendSolver {
+    Backtracking(IncrementalSolver),
+    Sat(MineSatSolver)
+}
+
+impl BackendSolver {
+    fn new(board: &Board, backend: SolverBackend) -> BackendSolver {
+        match backend {
+            SolverBackend::Backtracking => BackendSolver::Backtracking(IncrementalSolver::new(board)),
+            SolverBackend::Sat => BackendSolver::Sat(build_sat_solver(board))
+        }
+    }
+
+    fn update(&mut self, board: &Board) {
+        match self {
+            BackendSolver::Backtracking(solver) => solver.update(board),
+            // rebuilding is cheap next to the sampling it does
+            BackendSolver::Sat(solver) => *solver = build_sat_solver(board)
+        }
+    }
+}
+
+impl Solver<Point, usize> for BackendSolver {
+    fn solve(&mut self) -> Option<HashMap<Point, usize>> {
+        match self {
+            BackendSolver::Backtracking(solver) => solver.solve(),
+            BackendSolver::Sat(solver) => solver.solve()
+        }
+    }
+
+    fn weight(&self, solution: &HashMap<Point, usize>) -> f64 {
+        match self {
+            BackendSolver::Backtracking(solver) => solver.weight(solution),
+            BackendSolver::Sat(solver) => solver.weight(solution)
+        }
+    }
+}
+
 
 pub struct NaiveAI {
-    move_queue: Vec<ActionType>,
+    move_queue: Vec<Hint>,
+    last_move: Option<Hint>,
     min_move_time: time::Duration,
-    max_move_time: time::Duration
+    max_move_time: time::Duration,
+    backend: SolverBackend,
+    solvers: Vec<BackendSolver>,
+    search: Arc<ParallelSearch>,
+    renderer: Renderer,
+    // print what it's thinking and slow down so people can follow along
+    verbose: bool,
+    // every step of every decision, when asked for
+    trace: Option<Vec<String>>,
+    // sampling already done on the current board, see think
+    pondering: Option<Pondering>
+}
+
+// layouts found for a board, which is told apart by its knowledge changes and unknown cells
+struct Pondering {
+    board: (usize, usize),
+    spent: time::Duration,
+    counts: SolutionCounts<Point, usize>
 }
 
 impl Agent for NaiveAI {
     fn generate_move(&mut self, board: &Board) -> ActionType {
-        let start = time::Instant::now();
+        let start = Instant::now();
         let result = match self.move_queue.pop(){
-            Some(action) => action,
+            Some(hint) => hint,
             None => {
                 self.move_queue = self.generate_next_moves(board);
                 self.move_queue.pop().expect("something weird happened and we have no moves")
             }
         };
-        let now = time::Instant::now();
+        let now = Instant::now();
         let elapsed = now - start;
-        println!("generated move in {:?}", elapsed);
+        if self.verbose {
+            println!("generated move in {:?}", elapsed);
+            println!("{}", result);
+        }
         if elapsed < self.min_move_time{
             thread::sleep(self.min_move_time - elapsed);
         }
-        result
+        let action = result.action;
+        self.last_move = Some(result);
+        action
+    }
+
+    fn reset(&mut self) {
+        self.move_queue.clear();
+        self.last_move = None;
+        self.solvers.clear();
+        self.pondering = None;
+    }
+
+    fn show_board(&mut self, board: &Board) {
+        println!("{}", self.renderer.render(board, &[]));
+    }
+
+    fn show_probabilities(&mut self, board: &Board, probabilities: &[(Point, f32)]) {
+        println!("{}", self.renderer.render(board, probabilities));
+    }
+
+    fn show_result(&mut self, board: &Board, won: bool) {
+        println!("{}", self.renderer.render(board, &[]));
+        println!("{}", if won { "you win!" } else { "you lose" });
     }
 }
 
 impl NaiveAI {
 
     pub fn new(min_move_time: u64, max_move_time: u64) -> NaiveAI{
-        let mut move_queue = Vec::with_capacity(4);
+        NaiveAI::new_with_backend(min_move_time, max_move_time, SolverBackend::Backtracking)
+    }
+
+    pub fn new_with_backend(min_move_time: u64, max_move_time: u64, backend: SolverBackend) -> NaiveAI{
+        let move_queue = Vec::with_capacity(4);
         let min_move_time = time::Duration::from_millis(min_move_time);
         let max_move_time = time::Duration::from_millis(max_move_time);
-        move_queue.push(ActionType::Click(Point(0, 0)));
-        NaiveAI{move_queue, min_move_time, max_move_time}
+        let search = ParallelSearch::shared();
+        NaiveAI{move_queue, last_move: None, min_move_time, max_move_time, backend, solvers: vec![], search,
+                renderer: Renderer::default(), verbose: true, trace: None, pondering: None}
+    }
+
+    // keep a log of how every move was decided, see trace()
+    pub fn with_trace(mut self, trace: bool) -> NaiveAI {
+        self.trace = if trace { Some(vec![]) } else { None };
+        self
+    }
+
+    pub fn trace(&self) -> &[String] {
+        self.trace.as_deref().unwrap_or(&[])
+    }
+
+    // the move generate_move returned last, with its reason
+    pub fn last_move(&self) -> Option<&Hint> {
+        self.last_move.as_ref()
     }
 
-    pub fn generate_next_moves(&self, board: &Board) -> Vec<ActionType>{
-        let safe_flags = NaiveAI::known_safe_flags(board);
-        if !safe_flags.is_empty() {
-            return safe_flags.iter().map(|point| ActionType::Flag(*point)).collect()
+    fn log(&mut self, line: impl FnOnce() -> String) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.push(line());
         }
+    }
+
+    pub fn with_verbose(mut self, verbose: bool) -> NaiveAI {
+        self.verbose = verbose;
+        self
+    }
 
-        let safe_clicks = NaiveAI::known_safe_clicks(board);
-        if !safe_clicks.is_empty() {
-            return safe_clicks.iter().map(|point| ActionType::Click(*point)).collect()
+    // one thread, on the caller's, for places that can't start any (wasm)
+    pub fn single_threaded(mut self) -> NaiveAI {
+        self.search = Arc::new(ParallelSearch::new(1));
+        self.solvers.clear();
+        self
+    }
+
+    // how the board and the move probabilities get printed
+    pub fn with_renderer(mut self, renderer: Renderer) -> NaiveAI {
+        self.renderer = renderer;
+        self
+    }
+
+    // The moves to make next with why, last one first. The rules go first, flags
+    // before clicks, then whatever sampling turns up and the solver proves, then a guess
+    pub fn generate_next_moves(&mut self, board: &Board) -> Vec<Hint>{
+        self.log(|| format!("deciding with {} unknown cells and {} mines left", board.unknown_count(), board.remaining_mines()));
+        if !board.initialized {
+            // open in the top left, or wherever the board starts if that corner is masked
+            let opening = board.size.points().first().map(|point| Hint::new(ActionType::Click(*point), Reason::Opening));
+            return self.logged(opening.into_iter().collect())
         }
 
-        let probabilities = self.get_monte_carlo_probabilities(board);
-        println!("probs are");
-        println!("{}", board.to_string_with_probabilities(&probabilities));
-        let mut actions: Vec<ActionType> = Vec::with_capacity(1);
+        // the shortcuts take every number at its word, noisy boards go straight to sampling
+        if board.clue_noise() == ClueNoise::Exact {
+            let (flags, clicks): (Vec<Hint>, Vec<Hint>) = hint::rule_hints(board).into_iter()
+                .partition(|hint| matches!(hint.action, ActionType::Flag(_)));
+            self.log(|| format!("rules: {} cells to flag, {} to click", flags.len(), clicks.len()));
+            // a flag action adds one flag, full cells need one for every mine they can hold
+            if !flags.is_empty() {
+                let flags = flags.iter()
+                    .flat_map(|hint| std::iter::repeat_n(hint.clone(), board.mines_per_cell()))
+                    .collect();
+                return self.logged(flags)
+            }
+            if !clicks.is_empty() {
+                return self.logged(clicks)
+            }
+        } else {
+            self.log(|| String::from("clues are noisy, skipping the rules"));
+        }
+
+        let counts = self.get_monte_carlo_counts(board);
+        let probabilities = NaiveAI::probabilities_from_counts(board, &counts);
+        if self.verbose {
+            println!("probs are");
+            println!("{}", self.renderer.render(board, &probabilities));
+        }
+        let mut moves: Vec<Hint> = Vec::with_capacity(1);
         let mut highest_proba = 0.0;
         let mut best_point: Option<Point> = None;
-        for (point, proba) in probabilities{
+        // what every sample agrees on is only a lead until the solver proves it
+        let mut encoding: Option<MineEncoding> = None;
+        let (mut candidates, mut proven) = (0, 0);
+        for (point, proba) in &probabilities{
+            let (point, proba) = (*point, *proba);
             if proba > highest_proba {
                 highest_proba = proba;
                 best_point = Some(point);
             }
-            if proba == 0.0 {
-                actions.push(ActionType::Click(point));
-            }
-            if proba as usize == 1 {
-                actions.push(ActionType::Flag(point));
+            // only flag once every sample agrees on how many mines are there
+            let agreed = if proba == 0.0 {
+                Some(0)
+            } else if proba as usize == 1 {
+                counts.as_ref()
+                    .and_then(|counts| (1..board.mines_per_cell() + 1).find(|mines| counts.frequency(&point, mines) == 1.0))
+            } else {
+                None
+            };
+            if let Some(mines) = agreed {
+                candidates += 1;
+                if encoding.get_or_insert_with(|| MineEncoding::from_board(board)).forces(&point, mines) {
+                    proven += 1;
+                    match mines {
+                        0 => moves.push(Hint::new(ActionType::Click(point), Reason::NeverMined)),
+                        _ => moves.extend(std::iter::repeat_n(Hint::new(ActionType::Flag(point), Reason::AlwaysMined), mines))
+                    }
+                }
             }
         }
-        let best_point = best_point.unwrap_or_else(|| self.get_random_valid_click(board));
-        if actions.is_empty() {
-            actions.push(ActionType::Flag(best_point))
+        self.log(|| format!("sampling: the solver proves {} of the {} cells every sample agrees on, over {} border cells",
+                            proven, candidates, probabilities.len()));
+        if moves.is_empty() {
+            let guess = match best_point {
+                Some(point) => Hint::new(ActionType::Flag(point), Reason::Guess{probability: highest_proba}),
+                None => Hint::new(ActionType::Flag(self.get_random_valid_click(board)), Reason::Random)
+            };
+            self.log(|| {
+                let lowest = probabilities.iter().map(|(_, proba)| *proba).fold(1.0f32, f32::min);
+                format!("guessing, border risks run from {:.3} to {:.3}", lowest, highest_proba)
+            });
+            moves.push(guess);
+        }
+        self.logged(moves)
+    }
+
+    // the moves come out last first
+    fn logged(&mut self, moves: Vec<Hint>) -> Vec<Hint> {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.extend(moves.iter().rev().map(|hint| format!("move {}", hint)));
+        }
+        moves
+    }
+
+    // the chance each border point holds at least one mine, empty if no sample could be found
+    pub fn mine_probabilities(&mut self, board: &Board) -> Vec<(Point, f32)> {
+        if !board.initialized {
+            return vec![]
+        }
+        let counts = self.get_monte_carlo_counts(board);
+        NaiveAI::probabilities_from_counts(board, &counts)
+    }
+
+    fn probabilities_from_counts(board: &Board, counts: &Option<SolutionCounts<Point, usize>>) -> Vec<(Point, f32)> {
+        match counts {
+            None => vec![],
+            Some(counts) => board.get_border_points().into_iter()
+                .map(|point| (point, 1.0 - counts.frequency(&point, &0)))
+                .collect()
+        }
+    }
+
+    // Samples for at most slice and keeps what it found, for callers that can't block for
+    // a whole move (a browser tab). The next move and mine_probabilities build on it and
+    // only sample for whatever is left of max_move_time. True once that time is spent or
+    // the sampling can't go any further
+    pub fn think(&mut self, board: &Board, slice: time::Duration) -> bool {
+        if !board.initialized {
+            return true
+        }
+        let mut pondering = self.take_pondering(board);
+        let slice = slice.min(self.max_move_time.saturating_sub(pondering.spent));
+        let started = Instant::now();
+        let deadline = started + slice;
+        pondering.counts = pondering.counts.merge(self.sample(board, deadline));
+        pondering.spent += started.elapsed();
+        // solvers that stop before the deadline have run out of layouts
+        let done = pondering.spent >= self.max_move_time || Instant::now() < deadline;
+        self.pondering = Some(pondering);
+        done
+    }
+
+    // mine probabilities from the sampling think has done so far, without sampling more
+    pub fn probabilities_so_far(&self, board: &Board) -> Vec<(Point, f32)> {
+        match &self.pondering {
+            Some(pondering) if pondering.board == NaiveAI::board_key(board) && pondering.counts.solutions > 0 =>
+                NaiveAI::probabilities_from_counts(board, &Some(pondering.counts.clone())),
+            _ => vec![]
+        }
+    }
+
+    fn board_key(board: &Board) -> (usize, usize) {
+        (board.knowledge_change_count(), board.unknown_count())
+    }
+
+    // what's been found for this board so far, or a fresh start
+    fn take_pondering(&mut self, board: &Board) -> Pondering {
+        let key = NaiveAI::board_key(board);
+        match self.pondering.take() {
+            Some(pondering) if pondering.board == key => pondering,
+            _ => Pondering{board: key, spent: time::Duration::from_secs(0), counts: SolutionCounts::new()}
+        }
+    }
+
+    fn sample(&mut self, board: &Board, deadline: Instant) -> SolutionCounts<Point, usize> {
+        // TODO: ok so this isn't really naive anymore is it
+        if self.solvers.is_empty() {
+            self.solvers = (0..self.search.threads()).map(|_| BackendSolver::new(board, self.backend)).collect();
+        } else {
+            self.solvers.iter_mut().for_each(|solver| solver.update(board));
         }
-        actions
+        self.search.sample(&mut self.solvers, deadline)
     }
 
     fn get_random_valid_click(&self, board: &Board) -> Point {
@@ -194,70 +628,182 @@ impl NaiveAI {
         *points.choose(&mut rand::thread_rng()).expect("There are no unknown points!")
     }
 
-    fn known_safe_flags(board: &Board) -> HashSet<Point> {
-        board.size.points().iter()
-             .filter(|point| board.retrieve_cell(point).knowledge.is_known())
-            .flat_map(|point| board.known_flaggable_neighbors(point))
-            .collect()
+    // how often each border point held each number of mines over the samples,
+    // None if no sample could be found
+    fn get_monte_carlo_counts(&mut self, board: &Board) -> Option<SolutionCounts<Point, usize>>{
+        // whatever think already found counts towards the time
+        let mut pondering = self.take_pondering(board);
+        let started = Instant::now();
+        let deadline = started + self.max_move_time.saturating_sub(pondering.spent);
+        pondering.counts = pondering.counts.merge(self.sample(board, deadline));
+        pondering.spent += started.elapsed();
+        let (solutions, spent) = (pondering.counts.solutions, pondering.spent);
+        self.log(|| format!("sampling: {} layouts in {:?}", solutions, spent));
+        let counts = pondering.counts.clone();
+        self.pondering = Some(pondering);
+        if self.verbose {
+            println!("We got {} rollouts", counts.solutions);
+            thread::sleep(time::Duration::from_millis(1000));
+        }
+        if counts.solutions == 0 {
+            return None
+        }
+        Some(counts)
     }
+}
+
+#[cfg(test)]
+mod ai_tests {
+    use super::*;
+    use super::super::board::BoardSize;
+    use super::super::board::Lattice;
+    use super::super::board::Neighborhood;
+    use super::super::board::Topology;
 
-    fn known_safe_clicks(board: &Board) -> HashSet<Point> {
-        board.size.points().iter()
-             .filter(|point| board.retrieve_cell(point).knowledge.is_known())
-            .flat_map(|point| board.known_safe_neighbors(point))
+    fn expected_constraint_sources(board: &Board) -> HashSet<Point> {
+        board.size.points().into_iter()
+            .filter(|point| board.retrieve_cell(point).is_known_unmined() && board.has_unknown_neighbors(point))
             .collect()
     }
 
+    fn check_incremental_solver(mut board: Board) {
+        board.probe(&Point(8, 8, 0));
+        let mut incremental = IncrementalSolver::new(&board);
+        for _ in 0..20 {
+            let moves = hint::rule_hints(&board);
+            if moves.is_empty() {
+                break
+            }
+            for hint in &moves {
+                match hint.action {
+                    ActionType::Flag(point) => (0..board.mines_per_cell()).for_each(|_| board.toggle_flag(&point)),
+                    ActionType::Click(point) => { board.probe(&point); },
+                    _ => {}
+                }
+            }
+            incremental.update(&board);
 
-    fn get_monte_carlo_probabilities(&self, board: &Board) -> Vec<(Point, f32)>{
-        let start = time::Instant::now();
+            let variables: HashSet<Point> = incremental.variables().into_iter().collect();
+            let border: HashSet<Point> = board.get_border_points().into_iter().collect();
+            assert_eq!(variables, border);
+            let sources: HashSet<Point> = incremental.constraint_sources().into_iter().collect();
+            assert_eq!(sources, expected_constraint_sources(&board));
+            assert!(incremental.solve().is_some());
+        }
+    }
 
-        // TODO: ok so this isn't really naive anymore is it
-        let counts: Arc<Mutex<HashMap<Point, usize>>> = Arc::new(Mutex::new(HashMap::new()));
-        let rollouts = Arc::new(Mutex::new(0));
-        let threads = 4;
-        let mut handles = vec![];
-        let border_points: Vec<Point> = board.get_border_points();
-        let max_move_time = self.max_move_time;
-        for _ in 0..threads {
-            let counts = Arc::clone(&counts);
-            let rollouts = Arc::clone(&rollouts);
-            let mut solver = build_constraint_solver(board);
-            let handle = thread::spawn(move || {
-                while time::Instant::now().duration_since(start) < max_move_time {
-                    let assignments = solver.backtrack().expect("failed to find a solution");
-                    let mut counts = counts.lock().unwrap();
-                    assignments.iter().for_each(|(point, mined)| {
-                        match mined {
-                            false => {},
-                            true => {
-                                if counts.contains_key(&point){
-                                    *counts.get_mut(&point).unwrap() += 1;
-                                }
-                                else{
-                                    counts.insert(*point, 1);
-                                }
-                            }
-                        }
-                    });
-                    let mut rollouts = rollouts.lock().unwrap();
-                    *rollouts += 1;
-                }
-            });
-            handles.push(handle);
-        }
-        for handle in handles {
-            handle.join().unwrap();
-        }
-        let counts = counts.lock().unwrap();
-        let rollouts = *rollouts.lock().unwrap();
-        println!("We got {} rollouts", rollouts);
-        thread::sleep(time::Duration::from_millis(1000));
-        border_points.into_iter()
-            .map(|point| {
-                let count = counts.get(&point).unwrap_or(&0);
-                (point, (*count as f32)/(rollouts as f32))
-            })
-            .collect()
+    #[test]
+    fn incremental_solver_tracks_board() {
+        check_incremental_solver(Board::new_from_ints(16, 16, 40).unwrap());
+    }
+
+    #[test]
+    fn incremental_solver_tracks_hex_torus() {
+        let size = BoardSize::with_lattice(16, 16, Lattice::Hex, Topology::Torus).unwrap();
+        check_incremental_solver(Board::new_from_size(size, 30).unwrap());
+    }
+
+    #[test]
+    fn incremental_solver_tracks_knight_board() {
+        let size = BoardSize::with_neighborhood(16, 16, Neighborhood::knight(), Topology::Rectangle);
+        check_incremental_solver(Board::new_from_size(size, 30).unwrap());
+    }
+
+    #[test]
+    fn incremental_solver_tracks_layered_board() {
+        let size = BoardSize::with_depth(10, 10, 2, Topology::Rectangle);
+        check_incremental_solver(Board::new_from_size(size, 10).unwrap());
+    }
+
+    #[test]
+    fn incremental_solver_tracks_multi_mine_board() {
+        let size = BoardSize::new(16, 16);
+        check_incremental_solver(Board::new_with_mines_per_cell(size, 30, 2).unwrap());
+    }
+
+    #[test]
+    fn backends_agree_with_multi_mine_clues() {
+        let mut board = Board::new_with_mines_per_cell(BoardSize::new(10, 10), 20, 2).unwrap();
+        board.probe(&Point(5, 5, 0));
+        for backend in [SolverBackend::Backtracking, SolverBackend::Sat].iter() {
+            let solution = build_solver(&board, *backend).solve().unwrap();
+            assert!(solution.values().all(|mines| *mines <= 2));
+            for point in expected_constraint_sources(&board) {
+                let mines: usize = board.neighbor_points(&point).iter()
+                    .filter_map(|neighbor| solution.get(neighbor))
+                    .sum();
+                let expected = board.retrieve_cell(&point).clue - board.count_assumed_mined_neighbors(&point);
+                assert_eq!(mines, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn backends_tolerate_noisy_clues() {
+        let size = BoardSize::new(10, 10);
+        let mut board = Board::new_from_size(size, 15).unwrap().with_clue_noise(ClueNoise::OffByOne(0.2)).unwrap();
+        board.probe(&Point(5, 5, 0));
+        for backend in [SolverBackend::Backtracking, SolverBackend::Sat].iter() {
+            let mut solver = build_solver(&board, *backend);
+            let solution = solver.solve().unwrap();
+            assert!(solver.weight(&solution) > 0.0);
+            for point in expected_constraint_sources(&board) {
+                let mines: usize = board.neighbor_points(&point).iter()
+                    .filter_map(|neighbor| solution.get(neighbor))
+                    .sum();
+                let clue = board.retrieve_cell(&point).clue - board.count_assumed_mined_neighbors(&point);
+                assert!(mines + 1 >= clue && mines <= clue + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn moves_carry_reasons() {
+        let mut ai = NaiveAI::new(0, 200).with_verbose(false).with_trace(true);
+        let mut board = Board::new_from_ints(4, 4, 2).unwrap();
+        ai.generate_move(&board);
+        assert_eq!(ai.last_move().map(|hint| hint.reason.clone()), Some(Reason::Opening));
+
+        // the top row shows 1 2 1, no single number settles the bottom row but together they do
+        board = Board::new_with_mines(BoardSize::new(3, 2), &[Point(1, 0, 0), Point(1, 2, 0)]).unwrap();
+        (0..3).for_each(|column| { board.probe(&Point(0, column, 0)); });
+        ai.reset();
+        let moves = ai.generate_next_moves(&board);
+        assert!(moves.iter().all(|hint| hint.is_forced()));
+        assert!(moves.contains(&Hint::new(ActionType::Click(Point(1, 1, 0)), Reason::NeverMined)));
+        assert!(moves.contains(&Hint::new(ActionType::Flag(Point(1, 0, 0)), Reason::AlwaysMined)));
+        assert!(ai.trace().iter().any(|line| line.starts_with("rules: 0 cells to flag, 0 to click")));
+        assert!(ai.trace().iter().any(|line| line.starts_with("sampling: ") && line.contains("layouts")));
+        assert!(ai.trace().iter().any(|line| line.starts_with("sampling: the solver proves 3 of the 3 cells")));
+        assert!(ai.trace().last().unwrap().starts_with("move "));
+
+        // once (1,0) is flagged the 1 at (0,0) is used up
+        board.toggle_flag(&Point(1, 0, 0));
+        let moves = ai.generate_next_moves(&board);
+        assert!(moves.iter().all(|hint| matches!(hint.reason, Reason::Satisfied{..} | Reason::Saturated{..})));
+    }
+
+    #[test]
+    fn thinking_in_slices() {
+        let mut board = Board::new_with_mines(BoardSize::new(6, 6), &[Point(1, 1, 0), Point(5, 5, 0)]).unwrap();
+        board.probe(&Point(0, 0, 0));
+        // every agent samples on the one pool unless it's told to stay on its own thread
+        assert!(Arc::ptr_eq(&NaiveAI::new(0, 100).search, &NaiveAI::new(0, 100).search));
+        let mut ai = NaiveAI::new(0, 100).with_verbose(false).single_threaded();
+        assert!(!Arc::ptr_eq(&ai.search, &ParallelSearch::shared()));
+        assert!(ai.probabilities_so_far(&board).is_empty());
+        let slice = time::Duration::from_millis(10);
+        let mut slices = 1;
+        while !ai.think(&board, slice) {
+            slices += 1;
+        }
+        assert!(slices > 1);
+        let so_far = ai.probabilities_so_far(&board);
+        assert_eq!(so_far.len(), 3);
+        // the time is spent, so this is what the slices found
+        assert_eq!(ai.mine_probabilities(&board), so_far);
+
+        board.probe(&Point(5, 0, 0));
+        assert!(ai.probabilities_so_far(&board).is_empty());
     }
 }
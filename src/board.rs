@@ -1,158 +1,727 @@
-use rand::thread_rng;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
 use rand::seq::SliceRandom;
 use std::collections::HashSet;
 use std::collections::HashMap;
 use std::fmt;
-use itertools::Itertools;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use super::render::Renderer;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Content {
-    Mine,
+    // how many mines the cell holds, always at least one
+    Mine(usize),
     Empty
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum KnowledgeState {
     Unknown,
-    Flag,
+    // how many mines the player thinks the cell holds
+    Flag(usize),
     Known
 }
 
 impl KnowledgeState {
     pub fn is_known(&self) -> bool{
-        match *self {
-            KnowledgeState::Known => true,
-            _ => false
-        }
+        matches!(*self, KnowledgeState::Known)
     }
 
     pub fn is_flag(&self) -> bool{
-        match *self {
-            KnowledgeState::Flag => true,
-            _ => false
-        }
+        matches!(*self, KnowledgeState::Flag(_))
     }
 
     pub fn is_unknown(&self) -> bool{
-        match *self {
-            KnowledgeState::Unknown => true,
-            _ => false
-        }
+        matches!(*self, KnowledgeState::Unknown)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Cell {
     pub content: Content,
+    // boards work this out again when they're deserialized, it can be left out
+    #[cfg_attr(feature = "serde", serde(default))]
     pub mined_neighbor_count: usize,
+    // the number shown once revealed, only differs from mined_neighbor_count with noisy clues
+    pub clue: usize,
     pub knowledge: KnowledgeState,
     pub point: Point
 }
 
 impl Cell {
     fn create_empty(point: Point) -> Cell {
-        Cell{content: Content::Empty, mined_neighbor_count: 0, knowledge: KnowledgeState::Unknown, point}
+        Cell{content: Content::Empty, mined_neighbor_count: 0, clue: 0, knowledge: KnowledgeState::Unknown, point}
     }
 
     pub fn toggle_flag(&mut self){
+        self.cycle_flag(1);
+    }
+
+    // adds a flag, going back to unknown once the cell holds more than max_flags
+    pub fn cycle_flag(&mut self, max_flags: usize){
         let new_state = match self.knowledge {
             KnowledgeState::Known => KnowledgeState::Known,
-            KnowledgeState::Flag => KnowledgeState::Unknown,
-            KnowledgeState::Unknown => KnowledgeState::Flag
-        }; 
+            KnowledgeState::Flag(flags) if flags >= max_flags => KnowledgeState::Unknown,
+            KnowledgeState::Flag(flags) => KnowledgeState::Flag(flags + 1),
+            KnowledgeState::Unknown => KnowledgeState::Flag(1)
+        };
         self.knowledge = new_state;
     }
 
-    fn is_assumed_mine(&self) -> bool {
+    fn assumed_mines(&self) -> usize {
         match (&self.knowledge, &self.content) {
-            (KnowledgeState::Unknown, _) => false,
-            (KnowledgeState::Flag, _) => true,
-            (KnowledgeState::Known, Content::Mine) => true,
-            _ => false
+            (KnowledgeState::Unknown, _) => 0,
+            (KnowledgeState::Flag(flags), _) => *flags,
+            (KnowledgeState::Known, Content::Mine(mines)) => *mines,
+            _ => 0
         }
     }
 
     pub fn is_known_unmined(&self) -> bool {
-        match (&self.knowledge, &self.content) {
-            (KnowledgeState::Known, Content::Empty) => true,
-            _ => false
+        matches!((&self.knowledge, &self.content), (KnowledgeState::Known, Content::Empty))
+    }
+}
+
+// How the numbers on the board relate to the real mine counts
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ClueNoise {
+    Exact,
+    // every number is independently one too high or one too low with this probability
+    OffByOne(f64)
+}
+
+impl ClueNoise {
+    // probabilities outside 0 to 1, or NaN, would panic when the numbers are drawn
+    fn check(&self) -> Result<(), BoardError> {
+        match *self {
+            ClueNoise::OffByOne(p) if !(0.0..=1.0).contains(&p) => Err(BoardError::InvalidNoise),
+            _ => Ok(())
         }
     }
 
-    fn to_str(&self) -> String {
-        match (&self.knowledge, &self.content) {
-            (KnowledgeState::Flag, _) => String::from("▶"),
-            (KnowledgeState::Unknown, _) => String::from("□"),
-            (_, Content::Mine) => String::from("X"),
-            (_, Content::Empty) => {
-                if self.mined_neighbor_count == 0{
-                    String::from("_")
+    fn apply(&self, count: usize, rng: &mut impl Rng) -> usize {
+        match *self {
+            ClueNoise::Exact => count,
+            ClueNoise::OffByOne(p) => {
+                if !rng.gen_bool(p) {
+                    count
+                } else if count == 0 || rng.gen_bool(0.5) {
+                    count + 1
+                } else {
+                    count - 1
                 }
-                else{
-                    self.mined_neighbor_count.to_string()
+            }
+        }
+    }
+
+    // the chance of a cell showing `clue` when `count` of its neighbours are mined
+    pub fn likelihood(&self, clue: usize, count: usize) -> f64 {
+        match *self {
+            ClueNoise::Exact => if clue == count { 1.0 } else { 0.0 },
+            ClueNoise::OffByOne(p) => {
+                if clue == count {
+                    1.0 - p
+                } else if clue == count + 1 {
+                    if count == 0 { p } else { p / 2.0 }
+                } else if clue + 1 == count {
+                    p / 2.0
+                } else {
+                    0.0
                 }
             }
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoardError {
+    // no playable cells, or cells that can't hold a mine
+    ZeroDimensions,
+    // the number of cells or places for mines doesn't fit in a usize
+    Overflow,
+    TooManyMines{mine_count: usize, capacity: usize},
+    // not even the luckiest first click leaves room for the mines
+    TooManyMinesForFirstClick{mine_count: usize, policy: FirstClickPolicy},
+    // off the board or masked out
+    OutOfBounds(Point),
+    // a mask needs exactly one entry per cell
+    MaskSize{expected: usize, found: usize},
+    // densities go from 0 to 1
+    InvalidDensity,
+    // so does the chance of a noisy number
+    InvalidNoise,
+    // a saved or deserialized board that can't be read or doesn't add up, says what
+    InvalidSave(String),
+    // hex rows alternate their shift, an odd number of them can't wrap around
+    OddHexTorus{height: usize},
+    // a neighbourhood offset further than any board reaches
+    OffsetTooFar{row: i64, column: i64}
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BoardError::ZeroDimensions => write!(f, "the board has no cells to put mines in"),
+            BoardError::Overflow => write!(f, "the board is too big"),
+            BoardError::TooManyMines{mine_count, capacity} =>
+                write!(f, "{} mines don't fit, the board has room for {}", mine_count, capacity),
+            BoardError::TooManyMinesForFirstClick{mine_count, policy} =>
+                write!(f, "{} mines don't fit around the first click with {:?}", mine_count, policy),
+            BoardError::OutOfBounds(point) => write!(f, "{} is not on the board", point),
+            BoardError::MaskSize{expected, found} =>
+                write!(f, "the mask has {} cells, the board has {}", found, expected),
+            BoardError::InvalidDensity => write!(f, "the mine density has to be between 0 and 1"),
+            BoardError::InvalidNoise => write!(f, "the chance of a number being off has to be between 0 and 1"),
+            BoardError::InvalidSave(problem) => write!(f, "not a saved board: {}", problem),
+            BoardError::OddHexTorus{height} => write!(f, "a hex torus needs an even number of rows, not {}", height),
+            BoardError::OffsetTooFar{row, column} =>
+                write!(f, "the neighborhood offset {},{} reaches further than any board", row, column)
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+// The standard levels, sizes are width x height
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    // 9x9 with 10 mines
+    Beginner,
+    // 16x16 with 40 mines
+    Intermediate,
+    // 30x16 with 99 mines
+    Expert,
+    Custom{width: usize, height: usize, mine_count: usize}
+}
+
+impl Difficulty {
+    // the preset names, or WIDTHxHEIGHT/MINES for a custom board
+    pub fn from_name(name: &str) -> Option<Difficulty> {
+        match name.to_lowercase().as_str() {
+            "beginner" => Some(Difficulty::Beginner),
+            "intermediate" => Some(Difficulty::Intermediate),
+            "expert" => Some(Difficulty::Expert),
+            custom => {
+                let (size, mine_count) = custom.split_once('/')?;
+                let (width, height) = size.split_once('x')?;
+                Some(Difficulty::Custom{width: width.parse().ok()?, height: height.parse().ok()?,
+                                        mine_count: mine_count.parse().ok()?})
+            }
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        match *self {
+            Difficulty::Beginner => 9,
+            Difficulty::Intermediate => 16,
+            Difficulty::Expert => 30,
+            Difficulty::Custom{width, ..} => width
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        match *self {
+            Difficulty::Beginner => 9,
+            Difficulty::Intermediate | Difficulty::Expert => 16,
+            Difficulty::Custom{height, ..} => height
+        }
+    }
+
+    pub fn mine_count(&self) -> usize {
+        match *self {
+            Difficulty::Beginner => 10,
+            Difficulty::Intermediate => 40,
+            Difficulty::Expert => 99,
+            Difficulty::Custom{mine_count, ..} => mine_count
+        }
+    }
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difficulty::Beginner => write!(f, "beginner"),
+            Difficulty::Intermediate => write!(f, "intermediate"),
+            Difficulty::Expert => write!(f, "expert"),
+            Difficulty::Custom{width, height, mine_count} => write!(f, "{}x{}/{}", width, height, mine_count)
+        }
+    }
+}
+
+// What the first click is protected from. Policies the board is too dense
+// for fall back to weaker ones, see weaker()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FirstClickPolicy {
+    // mines can be anywhere, the first click can lose
+    Unprotected,
+    SafeCell,
+    // the first click is a zero, so it opens up some of the board
+    SafeOpening,
+    // nothing within this distance of the first click
+    SafeRadius(usize),
+    // mines can be anywhere but one under the first click is moved to the
+    // top left corner, or the first free cell to the right of it
+    WindowsClassic
+}
+
+// nothing within 2 cells of the first click
+impl Default for FirstClickPolicy {
+    fn default() -> FirstClickPolicy {
+        FirstClickPolicy::SafeRadius(2)
+    }
+}
+
+impl FirstClickPolicy {
+    // as saves write them: unprotected, safe-cell, safe-opening, safe-radius N or windows-classic
+    pub fn from_name(name: &str) -> Option<FirstClickPolicy> {
+        let words: Vec<String> = name.split_whitespace().map(str::to_lowercase).collect();
+        match words.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+            ["unprotected"] => Some(FirstClickPolicy::Unprotected),
+            ["safe-cell"] => Some(FirstClickPolicy::SafeCell),
+            ["safe-opening"] => Some(FirstClickPolicy::SafeOpening),
+            ["safe-radius", radius] => radius.parse().ok().map(FirstClickPolicy::SafeRadius),
+            ["windows-classic"] => Some(FirstClickPolicy::WindowsClassic),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            FirstClickPolicy::Unprotected => String::from("unprotected"),
+            FirstClickPolicy::SafeCell => String::from("safe-cell"),
+            FirstClickPolicy::SafeOpening => String::from("safe-opening"),
+            FirstClickPolicy::SafeRadius(radius) => format!("safe-radius {}", radius),
+            FirstClickPolicy::WindowsClassic => String::from("windows-classic")
+        }
+    }
+
+    // one step down: SafeRadius(n), ..., SafeRadius(1), SafeOpening, SafeCell, Unprotected
+    pub fn weaker(&self) -> Option<FirstClickPolicy> {
+        match *self {
+            FirstClickPolicy::Unprotected => None,
+            FirstClickPolicy::SafeCell => Some(FirstClickPolicy::Unprotected),
+            FirstClickPolicy::SafeOpening => Some(FirstClickPolicy::SafeCell),
+            FirstClickPolicy::SafeRadius(0) => Some(FirstClickPolicy::SafeCell),
+            FirstClickPolicy::SafeRadius(1) => Some(FirstClickPolicy::SafeOpening),
+            FirstClickPolicy::SafeRadius(radius) => Some(FirstClickPolicy::SafeRadius(radius - 1)),
+            FirstClickPolicy::WindowsClassic => Some(FirstClickPolicy::Unprotected)
+        }
+    }
+}
+
+// Point(row, column, layer), flat boards only use layer 0
 #[derive(Debug, PartialEq, Eq, Clone, Hash, Copy)]
-pub struct Point(pub usize, pub usize);
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Point(pub usize, pub usize, pub usize);
 
 impl Point {
+    // Only right for square rectangular boards, BoardSize::distance knows the board shape
     pub fn distance(&self, other: &Point) -> usize{
         //l-inf norm seems most appropriate for minesweeper
-        (self.0 as i64 - other.0 as i64).abs().max((self.1 as i64 - other.1 as i64).abs()) as usize
+        let axis = |a: usize, b: usize| (a as i64 - b as i64).unsigned_abs() as usize;
+        axis(self.0, other.0).max(axis(self.1, other.1)).max(axis(self.2, other.2))
     }
 
 }
 
 impl fmt::Display for Point {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Point({},{})", self.0, self.1)
+        if self.2 == 0 {
+            write!(f, "Point({},{})", self.0, self.1)
+        } else {
+            write!(f, "Point({},{},{})", self.0, self.1, self.2)
+        }
+    }
+}
+
+// How the edges of the board behave
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Topology {
+    Rectangle,
+    // neighbours wrap around both edges
+    Torus
+}
+
+// The cell grid. Hex boards use offset coordinates: Point(row, column)
+// with every odd row shifted half a cell to the right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Lattice {
+    Square,
+    Hex
+}
+
+// the most offsets a saved neighbourhood can list, enough for radius 31
+const MAX_NEIGHBORHOOD: usize = 4096;
+
+// Further than a board that fits in memory can reach, and far enough from the
+// i64 limits that negating an offset or adding a cell to it can't overflow
+const MAX_OFFSET: u64 = 1 << 32;
+
+// The (row, column) offsets a number counts on a square lattice. Counting goes
+// both ways, a mine is counted by every cell it is a neighbour of, so the
+// offsets are always made symmetric.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Neighborhood {
+    offsets: Vec<(i64, i64)>
+}
+
+impl Neighborhood {
+    // Offsets further than MAX_OFFSET in either direction are an OffsetTooFar
+    pub fn from_offsets(offsets: &[(i64, i64)]) -> Result<Neighborhood, BoardError> {
+        let too_far = |(x, y): &&(i64, i64)| x.unsigned_abs() > MAX_OFFSET || y.unsigned_abs() > MAX_OFFSET;
+        if let Some((x, y)) = offsets.iter().find(too_far) {
+            return Err(BoardError::OffsetTooFar{row: *x, column: *y})
+        }
+        Ok(Neighborhood::symmetric(offsets))
+    }
+
+    // the offsets and their opposites, once each, for offsets known to be in range
+    fn symmetric(offsets: &[(i64, i64)]) -> Neighborhood {
+        let mut symmetric: Vec<(i64, i64)> = Vec::with_capacity(offsets.len() * 2);
+        let mut seen: HashSet<(i64, i64)> = HashSet::with_capacity(offsets.len() * 2);
+        for (x, y) in offsets {
+            for offset in [(*x, *y), (-x, -y)].iter() {
+                if *offset != (0, 0) && seen.insert(*offset) {
+                    symmetric.push(*offset);
+                }
+            }
+        }
+        Neighborhood{offsets: symmetric}
+    }
+
+    // Offsets read from a save or deserialized. They can't reach further than the
+    // board is wide or high, and there can only be so many of them
+    fn checked(offsets: &[(i64, i64)], width: usize, height: usize) -> Result<Neighborhood, BoardError> {
+        if offsets.len() > MAX_NEIGHBORHOOD {
+            return Err(BoardError::InvalidSave(format!("{} neighborhood offsets, the most is {}", offsets.len(), MAX_NEIGHBORHOOD)))
+        }
+        let reaches_off = |(x, y): &&(i64, i64)| x.unsigned_abs() > height as u64 || y.unsigned_abs() > width as u64;
+        if let Some((x, y)) = offsets.iter().find(reaches_off) {
+            return Err(BoardError::InvalidSave(format!("the neighborhood offset {},{} reaches off the board", x, y)))
+        }
+        Ok(Neighborhood::symmetric(offsets))
+    }
+
+    // every cell within the given l-inf radius, radius 1 is the classic 8 neighbours
+    pub fn radius(radius: i64) -> Neighborhood {
+        let mut offsets = vec![];
+        for i in -radius..radius+1 {
+            for j in -radius..radius+1 {
+                offsets.push((i, j));
+            }
+        }
+        Neighborhood::symmetric(&offsets)
+    }
+
+    pub fn moore() -> Neighborhood {
+        Neighborhood::radius(1)
+    }
+
+    pub fn orthogonal() -> Neighborhood {
+        Neighborhood::symmetric(&[(-1, 0), (0, -1)])
+    }
+
+    pub fn knight() -> Neighborhood {
+        Neighborhood::symmetric(&[(-2, -1), (-2, 1), (-1, -2), (-1, 2)])
+    }
+
+    pub fn offsets(&self) -> &[(i64, i64)] {
+        &self.offsets
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct BoardSize {
     width: usize,
-    height: usize
+    height: usize,
+    depth: usize,
+    topology: Topology,
+    lattice: Lattice,
+    neighborhood: Option<Neighborhood>,
+    // one entry per integer_from_point index, false cells are not part of the board
+    mask: Option<Vec<bool>>
 }
 
 impl BoardSize {
+    pub fn new(width: usize, height: usize) -> BoardSize {
+        BoardSize::with_topology(width, height, Topology::Rectangle)
+    }
+
+    pub fn with_topology(width: usize, height: usize, topology: Topology) -> BoardSize {
+        BoardSize{width, height, depth: 1, topology, lattice: Lattice::Square, neighborhood: None, mask: None}
+    }
+
+    // Odd rows are shifted, so on a torus the last row and the first have to differ
+    // in shift or the neighbours stop being mutual where they wrap
+    pub fn with_lattice(width: usize, height: usize, lattice: Lattice, topology: Topology) -> Result<BoardSize, BoardError> {
+        let size = BoardSize{width, height, depth: 1, topology, lattice, neighborhood: None, mask: None};
+        size.check_wrapping()?;
+        Ok(size)
+    }
+
+    fn check_wrapping(&self) -> Result<(), BoardError> {
+        if self.lattice == Lattice::Hex && self.topology == Topology::Torus && self.height % 2 == 1 {
+            return Err(BoardError::OddHexTorus{height: self.height})
+        }
+        Ok(())
+    }
+
+    // a square board where numbers count the given neighbourhood instead of the 8 surrounding cells
+    pub fn with_neighborhood(width: usize, height: usize, neighborhood: Neighborhood, topology: Topology) -> BoardSize {
+        BoardSize{width, height, depth: 1, topology, lattice: Lattice::Square, neighborhood: Some(neighborhood), mask: None}
+    }
+
+    // a stack of square layers, every cell touches the 26 around it
+    pub fn with_depth(width: usize, height: usize, depth: usize, topology: Topology) -> BoardSize {
+        BoardSize{width, height, depth, topology, lattice: Lattice::Square, neighborhood: None, mask: None}
+    }
+
+    // a flat square board drawn as text, one line per row. '.' is a playable cell,
+    // anything else (or a missing character at the end of a short line) is a hole
+    pub fn from_pattern(pattern: &str, topology: Topology) -> Result<BoardSize, BoardError> {
+        let rows: Vec<&str> = pattern.lines().collect();
+        let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+        let mut mask = Vec::with_capacity(width * rows.len());
+        for row in &rows {
+            let mut playable: Vec<bool> = row.chars().map(|c| c == '.').collect();
+            playable.resize(width, false);
+            mask.extend(playable);
+        }
+        BoardSize::with_topology(width, rows.len(), topology).with_mask(mask)
+    }
+
+    // restricts any board to the cells marked true, indexed like integer_from_point
+    pub fn with_mask(mut self, mask: Vec<bool>) -> Result<BoardSize, BoardError> {
+        let expected = self.checked_area().ok_or(BoardError::Overflow)?;
+        if mask.len() != expected {
+            return Err(BoardError::MaskSize{expected, found: mask.len()})
+        }
+        self.mask = Some(mask);
+        Ok(self)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    pub fn lattice(&self) -> Lattice {
+        self.lattice
+    }
+
+    pub fn neighborhood(&self) -> Option<&Neighborhood> {
+        self.neighborhood.as_ref()
+    }
+
     pub fn area(&self) -> usize {
+        self.layer_area() * self.depth
+    }
+
+    fn checked_area(&self) -> Option<usize> {
+        self.width.checked_mul(self.height)?.checked_mul(self.depth)
+    }
+
+    pub fn layer_area(&self) -> usize {
         self.width * self.height
     }
 
+    // the number of cells that are actually part of the board
+    pub fn playable_area(&self) -> usize {
+        match &self.mask {
+            None => self.area(),
+            Some(mask) => mask.iter().filter(|playable| **playable).count()
+        }
+    }
+
     pub fn points(&self) -> Vec<Point> {
-        (0..self.area()).filter_map(|x| self.point_from_integer(x)).collect()
+        (0..self.area()).filter_map(|x| self.point_from_integer(x))
+            .filter(|point| self.is_playable(point))
+            .collect()
     }
 
     pub fn point_from_integer(&self, x: usize) -> Option<Point> {
         if x >= self.area() {
             return None
         }
-        Some(Point(x/self.width, x%self.width))
+        let in_layer = x % self.layer_area();
+        Some(Point(in_layer/self.width, in_layer%self.width, x/self.layer_area()))
     }
 
     pub fn integer_from_point(&self, point: &Point) -> Option<usize> {
-        let x = point.0*self.width + point.1 % self.width;
-        if x > self.area(){
+        if point.0 >= self.height || point.1 >= self.width || point.2 >= self.depth {
             None
         } else {
-            Some(x)
+            Some(point.2*self.layer_area() + point.0*self.width + point.1)
         }
     }
 
     pub fn point_is_in_bounds(&self, point: &Point) -> bool {
         self.integer_from_point(point).is_some()
     }
+
+    // in bounds and not masked out
+    pub fn is_playable(&self, point: &Point) -> bool {
+        match (self.integer_from_point(point), &self.mask) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(index), Some(mask)) => mask[index]
+        }
+    }
+
+    fn neighbor_offsets(&self, point: &Point) -> Vec<(i64, i64, i64)> {
+        if let Some(neighborhood) = &self.neighborhood {
+            return neighborhood.offsets().iter().map(|(x, y)| (*x, *y, 0)).collect()
+        }
+        match self.lattice {
+            Lattice::Square => {
+                let layers = if self.depth > 1 { -1..2 } else { 0..1 };
+                let mut product = Vec::with_capacity(26);
+                for k in layers {
+                    for (i, j) in Neighborhood::moore().offsets {
+                        product.push((i, j, k));
+                    }
+                    if k != 0 {
+                        product.push((0, 0, k));
+                    }
+                }
+                product
+            },
+            Lattice::Hex => {
+                let offsets = if point.0 % 2 == 1 {
+                    vec![(-1, 0), (-1, 1), (0, -1), (0, 1), (1, 0), (1, 1)]
+                } else {
+                    vec![(-1, -1), (-1, 0), (0, -1), (0, 1), (1, -1), (1, 0)]
+                };
+                offsets.into_iter().map(|(x, y)| (x, y, 0)).collect()
+            }
+        }
+    }
+
+    pub fn neighbor_points(&self, point: &Point) -> Vec<Point>{
+        let product = self.neighbor_offsets(point);
+        let (height, width, depth) = (self.height as i64, self.width as i64, self.depth as i64);
+        let neighbors = product.iter()
+               .map(|(x, y, z)| (x+(point.0 as i64), y+(point.1 as i64), z+(point.2 as i64)));
+        match self.topology {
+            Topology::Rectangle => neighbors
+                .filter(|(x, y, z)| *x >= 0 && *x < height && *y >= 0 && *y < width && *z >= 0 && *z < depth)
+                .map(|(x, y, z)| Point(x as usize, y as usize, z as usize))
+                .filter(|neighbor| self.is_playable(neighbor))
+                .collect(),
+            Topology::Torus => {
+                // on narrow boards several offsets wrap onto the same cell
+                let mut result: Vec<Point> = Vec::with_capacity(product.len());
+                neighbors
+                    .map(|(x, y, z)| Point(x.rem_euclid(height) as usize, y.rem_euclid(width) as usize, z.rem_euclid(depth) as usize))
+                    .filter(|neighbor| neighbor != point && self.is_playable(neighbor))
+                    .for_each(|neighbor| if !result.contains(&neighbor) { result.push(neighbor) });
+                result
+            }
+        }
+    }
+
+    // Every playable point at most radius steps from point. Only the box around the
+    // point can be that close, so that is all that gets looked at
+    pub fn points_within(&self, point: &Point, radius: usize) -> HashSet<Point> {
+        let reach = |x: usize, size: usize| -> Vec<usize> {
+            match self.topology {
+                Topology::Rectangle => (x.saturating_sub(radius)..=x.saturating_add(radius).min(size - 1)).collect(),
+                Topology::Torus if radius.saturating_mul(2) < size => (0..=2 * radius).map(|d| (x + size - radius + d) % size).collect(),
+                Topology::Torus => (0..size).collect()
+            }
+        };
+        let mut within = HashSet::new();
+        for layer in reach(point.2, self.depth) {
+            for row in reach(point.0, self.height) {
+                for column in reach(point.1, self.width) {
+                    let other = Point(row, column, layer);
+                    if self.is_playable(&other) && self.distance(point, &other) <= radius {
+                        within.insert(other);
+                    }
+                }
+            }
+        }
+        within
+    }
+
+    // The fewest playable points any point has within radius, itself included.
+    // On a full square board that's a corner of a rectangle or any cell of a torus,
+    // and the box around it is exactly what's within, so it's worked out per axis.
+    // Other boards are scanned, unless the radius reaches right across them
+    pub(crate) fn fewest_within(&self, radius: usize) -> usize {
+        if self.lattice == Lattice::Square && self.mask.is_none() {
+            let reach = |size: usize| match self.topology {
+                Topology::Rectangle => radius.saturating_add(1).min(size),
+                Topology::Torus => radius.saturating_mul(2).saturating_add(1).min(size)
+            };
+            return reach(self.width) * reach(self.height) * reach(self.depth)
+        }
+        // no two cells are further apart than this, whatever the lattice
+        if radius >= self.width + self.height + self.depth {
+            return self.playable_area()
+        }
+        self.points().iter().map(|point| self.points_within(point, radius).len()).min().unwrap_or(0)
+    }
+
+    // the number of steps between neighbours it takes to get from a to b,
+    // measured around the edges on a torus
+    pub fn distance(&self, a: &Point, b: &Point) -> usize {
+        match (self.lattice, self.topology) {
+            (Lattice::Square, Topology::Rectangle) => a.distance(b),
+            (Lattice::Square, Topology::Torus) => {
+                let wrapped = |x: usize, y: usize, size: usize| {
+                    let d = (x as i64 - y as i64).unsigned_abs() as usize;
+                    d.min(size.saturating_sub(d))
+                };
+                wrapped(a.0, b.0, self.height).max(wrapped(a.1, b.1, self.width)).max(wrapped(a.2, b.2, self.depth))
+            },
+            (Lattice::Hex, Topology::Rectangle) => hex_distance((a.0 as i64, a.1 as i64), (b.0 as i64, b.1 as i64)),
+            (Lattice::Hex, Topology::Torus) => {
+                let (height, width) = (self.height as i64, self.width as i64);
+                let mut shortest = usize::MAX;
+                for i in -1..2 {
+                    for j in -1..2 {
+                        let shifted = (b.0 as i64 + i * height, b.1 as i64 + j * width);
+                        shortest = shortest.min(hex_distance((a.0 as i64, a.1 as i64), shifted));
+                    }
+                }
+                shortest
+            }
+        }
+    }
 }
 
-fn sample_points(size: &BoardSize, n: usize, disallowed: &Point, disallowed_radius: usize) -> Option<Vec<Point>>{
-    let mut possible: Vec<usize> = (0..size.area()).collect();
-    possible.shuffle(&mut thread_rng());
-    let possible: Vec<Point> = possible.iter().map(|&x| size.point_from_integer(x).expect("bad size!"))
-                   .filter(|x| disallowed.distance(x) > disallowed_radius).take(n).collect();
+// n mines spread over the board outside of `avoid` with at most mines_per_cell
+// in any cell, a point appears once for every mine it holds
+fn sample_points(size: &BoardSize, n: usize, avoid: &HashSet<Point>, mines_per_cell: usize, rng: &mut impl Rng) -> Option<Vec<Point>>{
+    let mut possible: Vec<Point> = size.points().into_iter()
+        .filter(|point| !avoid.contains(point))
+        .flat_map(|point| std::iter::repeat_n(point, mines_per_cell))
+        .collect();
+    possible.shuffle(rng);
+    let possible: Vec<Point> = possible.into_iter().take(n).collect();
     if possible.len() == n {
         Some(possible)
     } else {
@@ -160,11 +729,33 @@ fn sample_points(size: &BoardSize, n: usize, disallowed: &Point, disallowed_radi
     }
 }
 
+// distance between two offset hex coordinates, via cube coordinates
+fn hex_distance(a: (i64, i64), b: (i64, i64)) -> usize {
+    let to_cube = |(row, column): (i64, i64)| {
+        let x = column - (row - (row & 1)) / 2;
+        (x, row, -x - row)
+    };
+    let (a, b) = (to_cube(a), to_cube(b));
+    (a.0 - b.0).abs().max((a.1 - b.1).abs()).max((a.2 - b.2).abs()) as usize
+}
+
+// Serialized as a BoardRecord, which is checked on the way back in
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "BoardRecord", into = "BoardRecord"))]
 pub struct Board {
     pub size: BoardSize,
     field: Vec<Cell>,
     pub mine_count: usize,
     pub initialized: bool,
+    mines_per_cell: usize,
+    clue_noise: ClueNoise,
+    first_click_policy: FirstClickPolicy,
+    // where the mines and noisy numbers come from, fresh randomness if None
+    seed: Option<u64>,
+    // the policy the first click actually got, once it has dealt
+    dealt_with: Option<FirstClickPolicy>,
+    knowledge_changes: Vec<Point>
 }
 
 impl fmt::Display for Board {
@@ -174,28 +765,68 @@ impl fmt::Display for Board {
 }
 
 impl Board {
-    pub fn new_from_ints(width: usize, height: usize, mine_count: usize) -> Option<Board>{
-        let size = BoardSize{width, height};
+    pub fn new_from_ints(width: usize, height: usize, mine_count: usize) -> Result<Board, BoardError>{
+        let size = BoardSize::new(width, height);
         Board::new_from_size(size, mine_count)
     }
 
-    pub fn new_with_mines(size: BoardSize, mines: &[Point]) -> Option<Board> {
-        if mines.iter().filter(|point| !size.point_is_in_bounds(point)).count() > 0 {
-            return None
+    pub fn new_from_difficulty(difficulty: Difficulty) -> Result<Board, BoardError>{
+        Board::new_from_ints(difficulty.width(), difficulty.height(), difficulty.mine_count())
+    }
+
+    // density is the fraction of playable cells holding a mine, rounded to the nearest cell
+    pub fn new_with_density(size: BoardSize, density: f64) -> Result<Board, BoardError> {
+        if !(0.0..=1.0).contains(&density) {
+            return Err(BoardError::InvalidDensity)
         }
-        let mut board = match Board::new_from_size(size, mines.len()){
-            None => return None,
-            Some(board) => board
-        };
-        board.initialized = true;
-        mines.iter().for_each( |point| {
-            board.set_point_as_mined(point);
-        });
-        Some(board)
+        let mine_count = (density * size.playable_area() as f64).round() as usize;
+        Board::new_from_size(size, mine_count)
+    }
+
+    // a point listed more than once holds that many mines
+    pub fn new_with_mines(size: BoardSize, mines: &[Point]) -> Result<Board, BoardError> {
+        size.checked_area().ok_or(BoardError::Overflow)?;
+        if let Some(point) = mines.iter().find(|point| !size.is_playable(point)) {
+            return Err(BoardError::OutOfBounds(*point))
+        }
+        let mut per_cell: HashMap<Point, usize> = HashMap::with_capacity(mines.len());
+        mines.iter().for_each(|point| *per_cell.entry(*point).or_insert(0) += 1);
+        let mines_per_cell = per_cell.values().copied().max().unwrap_or(1);
+        // dealt straight away, no first click to keep clear
+        let mut board = Board::new_blank(size, mines.len(), mines_per_cell)?;
+        let mut rng = board.rng();
+        board.initialize_with_mines(mines, &mut rng);
+        Ok(board)
+    }
+
+    pub fn new_from_size(size: BoardSize, mine_count: usize) -> Result<Board, BoardError> {
+        Board::new_with_mines_per_cell(size, mine_count, 1)
     }
 
-    pub fn new_from_size(size: BoardSize, mine_count: usize) -> Option<Board> {
-        if mine_count > size.area() {return None}; //TODO: this is too liberal
+    // the variant where a cell can hold several mines and numbers count all of them
+    pub fn new_with_mines_per_cell(size: BoardSize, mine_count: usize, mines_per_cell: usize) -> Result<Board, BoardError> {
+        Board::new_with_first_click_policy(size, mine_count, mines_per_cell, FirstClickPolicy::default())
+    }
+
+    // Every constructor checks its mines fit around the first click, this one is for
+    // boards too dense for the default of nothing within 2 cells
+    pub fn new_with_first_click_policy(size: BoardSize, mine_count: usize, mines_per_cell: usize,
+                                       first_click_policy: FirstClickPolicy) -> Result<Board, BoardError> {
+        Board::new_blank(size, mine_count, mines_per_cell)?.with_first_click_policy(first_click_policy)
+    }
+
+    // only checks the mines fit on the board, for boards dealt straight away or that
+    // get their first click policy from somewhere else
+    fn new_blank(size: BoardSize, mine_count: usize, mines_per_cell: usize) -> Result<Board, BoardError> {
+        size.checked_area().ok_or(BoardError::Overflow)?;
+        size.check_wrapping()?;
+        if size.playable_area() == 0 || mines_per_cell == 0 {
+            return Err(BoardError::ZeroDimensions)
+        }
+        let capacity = size.playable_area().checked_mul(mines_per_cell).ok_or(BoardError::Overflow)?;
+        if mine_count > capacity {
+            return Err(BoardError::TooManyMines{mine_count, capacity})
+        }
         let initialized = false;
         let mut field = Vec::with_capacity(size.height);
         for i in 0..size.area() {
@@ -203,7 +834,118 @@ impl Board {
             field.push(Cell::create_empty(point));
         }
 
-        Some(Board {size, field, mine_count, initialized})
+        let knowledge_changes = vec![];
+        let clue_noise = ClueNoise::Exact;
+        let first_click_policy = FirstClickPolicy::default();
+        let seed = None;
+        let dealt_with = None;
+        Ok(Board {size, field, mine_count, initialized, mines_per_cell, clue_noise, first_click_policy, seed, dealt_with, knowledge_changes})
+    }
+
+    // only matters until the first probe places the mines. A policy is turned down
+    // if it can't be kept wherever the first click lands, an unlucky first click
+    // falls back to a weaker policy instead
+    pub fn with_first_click_policy(mut self, first_click_policy: FirstClickPolicy) -> Result<Board, BoardError> {
+        if !self.initialized && !self.first_click_fits(first_click_policy) {
+            return Err(BoardError::TooManyMinesForFirstClick{mine_count: self.mine_count, policy: first_click_policy})
+        }
+        self.first_click_policy = first_click_policy;
+        Ok(self)
+    }
+
+    // whether the mines fit around the luckiest first click
+    fn first_click_fits(&self, policy: FirstClickPolicy) -> bool {
+        let fewest = match policy {
+            FirstClickPolicy::Unprotected | FirstClickPolicy::WindowsClassic => 0,
+            FirstClickPolicy::SafeCell => 1,
+            FirstClickPolicy::SafeRadius(radius) => self.size.fewest_within(radius),
+            FirstClickPolicy::SafeOpening => self.size.points().iter()
+                .map(|point| self.first_click_avoid(policy, point).len())
+                .min()
+                .unwrap_or(0)
+        };
+        let room = self.size.playable_area() - fewest;
+        room.saturating_mul(self.mines_per_cell) >= self.mine_count
+    }
+
+    // the same seed and first click always deal the same mines
+    pub fn with_seed(mut self, seed: u64) -> Board {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    // StdRng is free to change between rand versions and platforms, ChaCha8 isn't,
+    // so a seed deals the same board everywhere
+    fn rng(&self) -> ChaCha8Rng {
+        match self.seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy()
+        }
+    }
+
+    // a new deal with the same size, mine count and rules, nothing placed yet
+    // and no seed, so it really is new
+    pub fn new_game(&self) -> Board {
+        let board = Board::new_blank(self.size.clone(), self.mine_count, self.mines_per_cell)
+            .expect("the settings worked for this board");
+        Board{clue_noise: self.clue_noise, first_click_policy: self.first_click_policy, ..board}
+    }
+
+    // This board as if it had been dealt like dealt, a later position of the same
+    // game: its mines and numbers, but what the player knew here
+    pub(crate) fn dealt_like(&self, dealt: &Board) -> Board {
+        let mut board = self.clone();
+        for (cell, other) in board.field.iter_mut().zip(&dealt.field) {
+            cell.content = other.content.clone();
+            cell.mined_neighbor_count = other.mined_neighbor_count;
+            cell.clue = other.clue;
+        }
+        board.initialized = dealt.initialized;
+        board.dealt_with = dealt.dealt_with;
+        board
+    }
+
+    // the other way, what the player knew here before any mines were dealt
+    pub(crate) fn undealt(&self) -> Board {
+        let mut board = self.clone();
+        for cell in board.field.iter_mut() {
+            cell.content = Content::Empty;
+            cell.mined_neighbor_count = 0;
+            cell.clue = 0;
+        }
+        board.initialized = false;
+        board.dealt_with = None;
+        board
+    }
+
+    pub fn first_click_policy(&self) -> FirstClickPolicy {
+        self.first_click_policy
+    }
+
+    // The policy the first click was dealt with, weaker than first_click_policy if the
+    // mines didn't fit around where it landed. None before that, or if the board was
+    // given its mines
+    pub fn dealt_with(&self) -> Option<FirstClickPolicy> {
+        self.dealt_with
+    }
+
+    // numbers are drawn when the mines are placed, so a board that already has
+    // its mines gets new ones
+    pub fn with_clue_noise(mut self, clue_noise: ClueNoise) -> Result<Board, BoardError> {
+        clue_noise.check()?;
+        self.clue_noise = clue_noise;
+        if self.initialized {
+            self.roll_clues(&mut self.rng());
+        }
+        Ok(self)
+    }
+
+    pub fn clue_noise(&self) -> ClueNoise {
+        self.clue_noise
     }
 
 
@@ -230,8 +972,12 @@ impl Board {
 
     pub fn found_mines(&self) -> usize{
         self.field.iter()
-            .filter(|cell| cell.is_assumed_mine())
-            .count()
+            .map(|cell| cell.assumed_mines())
+            .sum()
+    }
+
+    pub fn mines_per_cell(&self) -> usize {
+        self.mines_per_cell
     }
 
     pub fn remaining_mines(&self) -> i32{
@@ -239,19 +985,15 @@ impl Board {
     }
 
     pub fn neighbor_points(&self, point: &Point) -> Vec<Point>{
-        let mut product = Vec::with_capacity(8);
-        for i in -1..2{
-            for j in -1..2{
-                if i != 0 || j != 0 {
-                    product.push((i, j))
-                }
-            }
-        }
-        product.iter()
-               .map(|(x, y)| (x+(point.0 as i32), y+(point.1 as i32)))
-               .filter(|(x, y)| *x >= 0 && *x < self.size.width as i32 && *y >= 0 && *y < self.size.height as i32)
-               .map(|(x, y)| Point(x as usize, y as usize))
-               .collect()
+        self.size.neighbor_points(point)
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.size.topology()
+    }
+
+    pub fn lattice(&self) -> Lattice {
+        self.size.lattice()
     }
 
     pub fn neighbor_cells_from_point(&self, point: &Point) -> Vec<&Cell>{
@@ -264,27 +1006,78 @@ impl Board {
 
     fn set_point_as_mined(&mut self, point: &Point){
         {
-            let mut cell =  self.retrieve_cell_mutable(point);
-            cell.content = Content::Mine;
+            let cell =  self.retrieve_cell_mutable(point);
+            cell.content = match cell.content {
+                Content::Mine(mines) => Content::Mine(mines + 1),
+                Content::Empty => Content::Mine(1)
+            };
         }
-        for neighbor in self.neighbor_points(&point){
-            let mut cell =  self.retrieve_cell_mutable(&neighbor);
+        for neighbor in self.neighbor_points(point){
+            let cell =  self.retrieve_cell_mutable(&neighbor);
             cell.mined_neighbor_count += 1;
         }
     }
 
     fn initialize_from_point(&mut self, point: &Point){
-        let mined_points = sample_points(&self.size, self.mine_count, point, 2).expect("failed to init mines");
-        self.initialize_with_mines(&mined_points);
+        let mut policy = self.first_click_policy;
+        let mut rng = self.rng();
+        let mined_points = loop {
+            match self.place_mines(policy, point, &mut rng) {
+                Some(mined_points) => break mined_points,
+                // the constructors make sure Unprotected always fits
+                None => policy = policy.weaker().expect("failed to init mines")
+            }
+        };
+        self.dealt_with = Some(policy);
+        self.initialize_with_mines(&mined_points, &mut rng);
     }
 
-    fn initialize_with_mines(&mut self, mined_points: &[Point]) {
+    // the cells a policy keeps clear of mines when the first click is at point
+    fn first_click_avoid(&self, policy: FirstClickPolicy, point: &Point) -> HashSet<Point> {
+        match policy {
+            FirstClickPolicy::Unprotected | FirstClickPolicy::WindowsClassic => HashSet::new(),
+            FirstClickPolicy::SafeCell => Some(*point).into_iter().collect(),
+            FirstClickPolicy::SafeOpening => self.neighbor_points(point).into_iter().chain(Some(*point)).collect(),
+            FirstClickPolicy::SafeRadius(radius) => self.size.points_within(point, radius)
+        }
+    }
+
+    fn place_mines(&self, policy: FirstClickPolicy, point: &Point, rng: &mut impl Rng) -> Option<Vec<Point>> {
+        let avoid = self.first_click_avoid(policy, point);
+        let mut mined_points = sample_points(&self.size, self.mine_count, &avoid, self.mines_per_cell, rng)?;
+        if policy == FirstClickPolicy::WindowsClassic {
+            let moved = mined_points.iter().filter(|mined| *mined == point).count();
+            let free = self.size.points().into_iter().filter(|other| other != point)
+                .flat_map(|other| {
+                    let held = mined_points.iter().filter(|mined| **mined == other).count();
+                    std::iter::repeat_n(other, self.mines_per_cell - held)
+                })
+                .take(moved)
+                .collect::<Vec<Point>>();
+            // a completely full board has nowhere to move them to
+            if free.len() == moved {
+                mined_points.retain(|mined| mined != point);
+                mined_points.extend(free);
+            }
+        }
+        Some(mined_points)
+    }
+
+    fn initialize_with_mines(&mut self, mined_points: &[Point], rng: &mut impl Rng) {
         // At this point we are assuming that all the points are valid
         // which seems maybe not ideal?
         mined_points.iter().for_each(|point| self.set_point_as_mined(point));
+        self.roll_clues(rng);
         self.initialized = true;
     }
 
+    fn roll_clues(&mut self, rng: &mut impl Rng) {
+        let clue_noise = self.clue_noise;
+        for cell in self.field.iter_mut() {
+            cell.clue = clue_noise.apply(cell.mined_neighbor_count, rng);
+        }
+    }
+
     pub fn get_unknown_points(&self) -> Vec<Point> {
         // TODO: very similar to get_border_points
         self.size.points().iter()
@@ -302,19 +1095,40 @@ impl Board {
             .collect()
     }
 
+    // with several mines per cell this steps through the flag counts
     pub fn toggle_flag(&mut self, point: &Point){
-        self.retrieve_cell_mutable(point).toggle_flag()
+        if !self.size.is_playable(point) {
+            return
+        }
+        let max_flags = self.mines_per_cell;
+        self.retrieve_cell_mutable(point).cycle_flag(max_flags);
+        self.knowledge_changes.push(*point);
     }
 
     pub fn flag_neighbors(&mut self, point: &Point){
+        if !self.size.is_playable(point) {
+            return
+        }
         let cell = self.retrieve_cell(point);
         let neighbors = self.neighbor_points(point);
         let ungood_points: Vec<&Point> = neighbors.iter()
             .filter(|point| !self.retrieve_cell(point).is_known_unmined())
             .collect();
-        if ungood_points.len() == cell.mined_neighbor_count{
+        // revealed mines hold what they hold, everything else could be full
+        let most_mines: usize = ungood_points.iter()
+            .map(|point| match (&self.retrieve_cell(point).knowledge, &self.retrieve_cell(point).content) {
+                (KnowledgeState::Known, Content::Mine(mines)) => *mines,
+                _ => self.mines_per_cell
+            })
+            .sum();
+        if most_mines == cell.clue{
+            let max_flags = self.mines_per_cell;
             for neighbor in ungood_points{
-                self.retrieve_cell_mutable(neighbor).knowledge = KnowledgeState::Flag;
+                if self.retrieve_cell(neighbor).knowledge.is_known() {
+                    continue
+                }
+                self.retrieve_cell_mutable(neighbor).knowledge = KnowledgeState::Flag(max_flags);
+                self.knowledge_changes.push(*neighbor);
             }
         }
     }
@@ -333,8 +1147,8 @@ impl Board {
 
     pub fn count_assumed_mined_neighbors(&self, point: &Point) -> usize{
         self.neighbor_cells_from_point(point).iter()
-            .filter(|neighbor| neighbor.is_assumed_mine())
-            .count()
+            .map(|neighbor| neighbor.assumed_mines())
+            .sum()
     }
 
     pub fn count_known_neighbors(&self, point: &Point) -> usize {
@@ -358,10 +1172,10 @@ impl Board {
     pub fn known_flaggable_neighbors(&self, point: &Point) -> Vec<Point> {
         let assumed_mined_neighbor_count = self.count_assumed_mined_neighbors(point);
         let unknown_neighbor_count = self.count_unknown_neighbors(point);
-        let mined_neighbor_count = self.retrieve_cell(point).mined_neighbor_count;
-        let remaining_mines = mined_neighbor_count as i32 - assumed_mined_neighbor_count as i32;
+        let clue = self.retrieve_cell(point).clue;
+        let remaining_mines = clue as i32 - assumed_mined_neighbor_count as i32;
 
-        if remaining_mines == unknown_neighbor_count as i32 {
+        if remaining_mines == (unknown_neighbor_count * self.mines_per_cell) as i32 {
             self.neighbor_points(point).into_iter()
                 .filter(|point| self.retrieve_cell(point).knowledge.is_unknown())
                 .collect()
@@ -373,10 +1187,10 @@ impl Board {
     pub fn known_safe_neighbors(&self, point: &Point) -> Vec<Point> {
         //TODO: so this is basically known_flaggable_neighbors
         let assumed_mined_neighbor_count = self.count_assumed_mined_neighbors(point);
-        let mined_neighbor_count = self.retrieve_cell(point).mined_neighbor_count;
-        let remaining_mines = mined_neighbor_count as i32 - assumed_mined_neighbor_count as i32;
+        let clue = self.retrieve_cell(point).clue;
+        let remaining_mines = clue as i32 - assumed_mined_neighbor_count as i32;
 
-        if remaining_mines == 0 as i32 {
+        if remaining_mines == 0 {
             self.neighbor_points(point).into_iter()
                 .filter(|point| self.retrieve_cell(point).knowledge.is_unknown())
                 .collect()
@@ -391,7 +1205,7 @@ impl Board {
             return 0
         }
         let mut hits = 0;
-        if self.count_assumed_mined_neighbors(point) == cell.mined_neighbor_count {
+        if self.count_assumed_mined_neighbors(point) == cell.clue {
             for neighbor in self.neighbor_points(point){
                 hits += self.probe(&neighbor);
             }
@@ -400,18 +1214,21 @@ impl Board {
     }
 
     pub fn probe(&mut self, point: &Point) -> usize{
+        if !self.size.is_playable(point) {
+            return 0
+        }
         if !&self.initialized {
             self.initialize_from_point(point);
         }
 
         // overall a lot of this seems bad
         let mut region = HashSet::with_capacity(16);
-        region.insert(point.clone());
-        self.find_region(point.clone(), &mut region);
+        region.insert(*point);
+        self.find_region(*point, &mut region);
 
         region.iter()
             .map(|point| match self.reveal_point(point).content{
-                    Content::Mine => {
+                    Content::Mine(_) => {
                         self.retrieve_cell(point).knowledge.is_known() as usize
                     },
                     Content::Empty => 0
@@ -423,10 +1240,11 @@ impl Board {
         let neighbors = self.neighbor_points(&point);
         let cell = self.retrieve_cell(&point);
         if let Content::Empty = cell.content {
-            if !cell.knowledge.is_known() && cell.mined_neighbor_count == 0 {
+            // a noisy zero or a noisy number next to a real zero doesn't open up
+            if !cell.knowledge.is_known() && cell.mined_neighbor_count == 0 && cell.clue == 0 {
                 for neighbor in neighbors{
                     if !acc.contains(&neighbor){
-                        acc.insert(neighbor.clone());
+                        acc.insert(neighbor);
                         self.find_region(neighbor, acc);
                     }
                 }
@@ -435,36 +1253,241 @@ impl Board {
     }
 
     fn reveal_point(&mut self, point: &Point) -> &Cell{
-        let mut cell = self.retrieve_cell_mutable(point);
-        if cell.knowledge.is_unknown(){
-            cell.knowledge = KnowledgeState::Known;
+        if self.retrieve_cell(point).knowledge.is_unknown(){
+            self.retrieve_cell_mutable(point).knowledge = KnowledgeState::Known;
+            self.knowledge_changes.push(*point);
         }
-        cell
+        self.retrieve_cell(point)
+    }
+
+    // Every point whose knowledge state changed, in order. Callers can keep
+    // the length around and pass it to knowledge_changes_since later.
+    pub fn knowledge_change_count(&self) -> usize {
+        self.knowledge_changes.len()
+    }
+
+    pub fn knowledge_changes_since(&self, count: usize) -> &[Point] {
+        &self.knowledge_changes[count.min(self.knowledge_changes.len())..]
     }
 
+    // the default Unicode rendering, render::Renderer has the other themes
     pub fn to_string_with_probabilities(&self, probabilities: &[(Point, f32)]) -> String {
-        let proba_lookup: HashMap<Point, f32> = probabilities.iter()
-            .map(|(p, f)| (*p, *f))
-            .collect();
-        let mut result = "  ".to_owned();
-        for i in 0..self.size.width{
-            result += &i.to_string()[..];
-        }
-        result += "\n";
-        for i in 0..self.size.height{
-            result += &i.to_string()[..];
-            result += " ";
-            for j in 0..self.size.width{
-                let cell = self.retrieve_cell(&Point(i, j));
-                let c = match proba_lookup.get(&cell.point){
-                    None => cell.to_str(),
-                    Some(p) => proba_to_char(*p)
-                };
-                result += &c[..];
+        Renderer::default().render(self, probabilities)
+    }
+
+    // A plain text snapshot of the game, one setting per line followed by one
+    // line per cell: mines, mined neighbours, clue and what the player knows
+    // ("?" unknown, "k" known, "f2" two flags). Masked cells are a single "-".
+    // Loading counts the mined neighbours again instead of reading them.
+    pub fn to_save_string(&self) -> String {
+        let mut lines = vec![String::from("minesweeper board")];
+        lines.push(format!("size {} {} {}", self.size.width, self.size.height, self.size.depth));
+        lines.push(String::from(match self.size.topology {
+            Topology::Rectangle => "topology rectangle",
+            Topology::Torus => "topology torus"
+        }));
+        lines.push(String::from(match self.size.lattice {
+            Lattice::Square => "lattice square",
+            Lattice::Hex => "lattice hex"
+        }));
+        if let Some(neighborhood) = &self.size.neighborhood {
+            let offsets: Vec<String> = neighborhood.offsets().iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+            lines.push(format!("neighborhood {}", offsets.join(" ")));
+        }
+        lines.push(format!("mines {} {}", self.mine_count, self.mines_per_cell));
+        lines.push(match self.clue_noise {
+            ClueNoise::Exact => String::from("noise exact"),
+            ClueNoise::OffByOne(p) => format!("noise off-by-one {}", p)
+        });
+        lines.push(format!("first-click {}", self.first_click_policy.name()));
+        if let Some(policy) = self.dealt_with {
+            lines.push(format!("dealt-with {}", policy.name()));
+        }
+        if let Some(seed) = self.seed {
+            lines.push(format!("seed {}", seed));
+        }
+        lines.push(format!("initialized {}", self.initialized));
+        for cell in &self.field {
+            if !self.size.is_playable(&cell.point) {
+                lines.push(String::from("-"));
+                continue
             }
-            result += "\n";
+            let mines = match cell.content {
+                Content::Mine(mines) => mines,
+                Content::Empty => 0
+            };
+            let knowledge = match cell.knowledge {
+                KnowledgeState::Unknown => String::from("?"),
+                KnowledgeState::Known => String::from("k"),
+                KnowledgeState::Flag(flags) => format!("f{}", flags)
+            };
+            lines.push(format!("{} {} {} {}", mines, cell.mined_neighbor_count, cell.clue, knowledge));
         }
-        result
+        lines.join("\n") + "\n"
+    }
+
+    pub fn from_save_string(text: &str) -> Result<Board, BoardError> {
+        let invalid = |problem: &str| BoardError::InvalidSave(String::from(problem));
+        let mut lines = text.lines();
+        if lines.next() != Some("minesweeper board") {
+            return Err(invalid("missing the header"))
+        }
+        let mut settings: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut cell_lines = vec![];
+        for line in lines {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some(key) if key.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    settings.insert(key, words.collect());
+                },
+                Some(_) => cell_lines.push(line),
+                None => {}
+            }
+        }
+        let setting = |key: &str| settings.get(key).ok_or_else(|| invalid(&format!("no {} line", key)));
+        let number = |word: Option<&&str>| word.and_then(|word| word.parse::<usize>().ok()).ok_or_else(|| invalid("bad number"));
+
+        let size = setting("size")?;
+        let (width, height, depth) = (number(size.first())?, number(size.get(1))?, number(size.get(2))?);
+        let topology = match setting("topology")?.first() {
+            Some(&"rectangle") => Topology::Rectangle,
+            Some(&"torus") => Topology::Torus,
+            _ => return Err(invalid("unknown topology"))
+        };
+        let lattice = match setting("lattice")?.first() {
+            Some(&"square") => Lattice::Square,
+            Some(&"hex") => Lattice::Hex,
+            _ => return Err(invalid("unknown lattice"))
+        };
+        let neighborhood = match settings.get("neighborhood") {
+            None => None,
+            Some(words) => {
+                let offsets: Option<Vec<(i64, i64)>> = words.iter()
+                    .map(|word| {
+                        let mut parts = word.split(',').map(|part| part.parse::<i64>().ok());
+                        Some((parts.next()??, parts.next()??))
+                    })
+                    .collect();
+                Some(Neighborhood::checked(&offsets.ok_or_else(|| invalid("bad neighborhood"))?, width, height)?)
+            }
+        };
+        let mines = setting("mines")?;
+        let (mine_count, mines_per_cell) = (number(mines.first())?, number(mines.get(1))?);
+        let noise = setting("noise")?;
+        let clue_noise = match (noise.first(), noise.get(1).and_then(|p| p.parse::<f64>().ok())) {
+            (Some(&"exact"), _) => ClueNoise::Exact,
+            (Some(&"off-by-one"), Some(p)) => ClueNoise::OffByOne(p),
+            _ => return Err(invalid("unknown noise"))
+        };
+        let first_click_policy = FirstClickPolicy::from_name(&setting("first-click")?.join(" "))
+            .ok_or_else(|| invalid("unknown first click policy"))?;
+        let dealt_with = match settings.get("dealt-with") {
+            None => None,
+            Some(words) => Some(FirstClickPolicy::from_name(&words.join(" ")).ok_or_else(|| invalid("unknown first click policy"))?)
+        };
+        let seed = match settings.get("seed") {
+            None => None,
+            Some(words) => Some(words.first().and_then(|word| word.parse::<u64>().ok()).ok_or_else(|| invalid("bad seed"))?)
+        };
+        let initialized = match setting("initialized")?.first() {
+            Some(&"true") => true,
+            Some(&"false") => false,
+            _ => return Err(invalid("initialized is true or false"))
+        };
+
+        let mut size = BoardSize{width, height, depth, topology, lattice, neighborhood, mask: None};
+        let area = size.checked_area().ok_or(BoardError::Overflow)?;
+        if cell_lines.len() != area {
+            return Err(invalid(&format!("{} cells for a board of {}", cell_lines.len(), area)))
+        }
+        if cell_lines.contains(&"-") {
+            size = size.with_mask(cell_lines.iter().map(|line| *line != "-").collect())?;
+        }
+        let mut board = Board::new_blank(size, mine_count, mines_per_cell)?;
+        for (cell, line) in board.field.iter_mut().zip(cell_lines) {
+            if line == "-" {
+                continue
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let mines = number(words.first())?;
+            if mines > mines_per_cell {
+                return Err(invalid("a cell holds too many mines"))
+            }
+            cell.content = if mines == 0 { Content::Empty } else { Content::Mine(mines) };
+            number(words.get(1))?;
+            cell.clue = number(words.get(2))?;
+            cell.knowledge = match words.get(3) {
+                Some(&"?") => KnowledgeState::Unknown,
+                Some(&"k") => KnowledgeState::Known,
+                Some(flags) if flags.starts_with('f') => KnowledgeState::Flag(number(Some(&&flags[1..]))?),
+                _ => return Err(invalid("unknown knowledge state"))
+            };
+        }
+        board.initialized = initialized;
+        board.clue_noise = clue_noise;
+        board.first_click_policy = first_click_policy;
+        board.dealt_with = dealt_with;
+        board.seed = seed;
+        board.checked()
+    }
+
+    // A board put together from outside, from a save or deserialized, has to add up:
+    // cells hold and show numbers that fit its rules and it has as many mines as it
+    // says. The mined neighbour counts are worked out here rather than trusted
+    fn checked(mut self) -> Result<Board, BoardError> {
+        let invalid = |problem: String| Err(BoardError::InvalidSave(problem));
+        self.clue_noise.check()?;
+        let mut mined = vec![];
+        for cell in &self.field {
+            let mines = match cell.content {
+                Content::Mine(mines) if mines == 0 || mines > self.mines_per_cell =>
+                    return invalid(format!("{} holds {} mines", cell.point, mines)),
+                Content::Mine(mines) => mines,
+                Content::Empty => 0
+            };
+            match cell.knowledge {
+                KnowledgeState::Flag(flags) if flags == 0 || flags > self.mines_per_cell =>
+                    return invalid(format!("{} has {} flags", cell.point, flags)),
+                KnowledgeState::Known if !self.initialized =>
+                    return invalid(format!("{} is revealed before the mines are placed", cell.point)),
+                _ => {}
+            }
+            if !self.size.is_playable(&cell.point) && (mines > 0 || !cell.knowledge.is_unknown()) {
+                return invalid(format!("{} is masked out but in use", cell.point))
+            }
+            if mines > 0 {
+                mined.push((cell.point, mines));
+            }
+        }
+        let total: usize = mined.iter().map(|(_, mines)| mines).sum();
+        if total != if self.initialized { self.mine_count } else { 0 } {
+            return invalid(format!("{} mines placed on a board of {}", total, self.mine_count))
+        }
+        if self.dealt_with.is_some() && !self.initialized {
+            return invalid(String::from("dealt before the mines are placed"))
+        }
+        if !self.initialized && !self.first_click_fits(self.first_click_policy) {
+            return Err(BoardError::TooManyMinesForFirstClick{mine_count: self.mine_count, policy: self.first_click_policy})
+        }
+
+        self.field.iter_mut().for_each(|cell| cell.mined_neighbor_count = 0);
+        for (point, mines) in mined {
+            for neighbor in self.neighbor_points(&point) {
+                self.retrieve_cell_mutable(&neighbor).mined_neighbor_count += mines;
+            }
+        }
+        for cell in &self.field {
+            let count = cell.mined_neighbor_count;
+            let fits = match self.clue_noise {
+                ClueNoise::Exact => cell.clue == count,
+                ClueNoise::OffByOne(_) => cell.clue + 1 >= count && cell.clue <= count + 1
+            };
+            if !fits {
+                return invalid(format!("{} shows {} next to {} mines", cell.point, cell.clue, count))
+            }
+        }
+        self.knowledge_changes.clear();
+        Ok(self)
     }
 
     pub fn is_won(&self) -> bool {
@@ -476,21 +1499,103 @@ impl Board {
     }
 }
 
-fn proba_to_char(proba: f32) -> String{
-    if proba == 0.0 {
-        String::from("◌")
-    } else if proba < 0.2 {
-        String::from("-")
-    } else if proba < 0.4 {
-        String::from("=")
-    } else if proba < 0.6 {
-        String::from("▤")
-    } else if proba < 0.8 {
-        String::from("▦")
-    } else if proba < 1.0 {
-        String::from("▩")
-    } else{
-        String::from("●")
+// How a Board is serialized: its settings and every cell, masked ones included
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct BoardRecord {
+    width: usize,
+    height: usize,
+    depth: usize,
+    topology: Topology,
+    lattice: Lattice,
+    neighborhood: Option<Vec<(i64, i64)>>,
+    mask: Option<Vec<bool>>,
+    mine_count: usize,
+    mines_per_cell: usize,
+    clue_noise: ClueNoise,
+    first_click_policy: FirstClickPolicy,
+    #[serde(default)]
+    dealt_with: Option<FirstClickPolicy>,
+    seed: Option<u64>,
+    initialized: bool,
+    cells: Vec<Cell>
+}
+
+#[cfg(feature = "serde")]
+impl From<Board> for BoardRecord {
+    fn from(board: Board) -> BoardRecord {
+        let size = board.size;
+        BoardRecord{
+            width: size.width, height: size.height, depth: size.depth, topology: size.topology, lattice: size.lattice,
+            neighborhood: size.neighborhood.map(|neighborhood| neighborhood.offsets),
+            mask: size.mask,
+            mine_count: board.mine_count, mines_per_cell: board.mines_per_cell, clue_noise: board.clue_noise,
+            first_click_policy: board.first_click_policy, dealt_with: board.dealt_with, seed: board.seed,
+            initialized: board.initialized,
+            cells: board.field
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::convert::TryFrom<BoardRecord> for Board {
+    type Error = BoardError;
+
+    fn try_from(record: BoardRecord) -> Result<Board, BoardError> {
+        let neighborhood = match record.neighborhood {
+            Some(offsets) => Some(Neighborhood::checked(&offsets, record.width, record.height)?),
+            None => None
+        };
+        let mut size = BoardSize{width: record.width, height: record.height, depth: record.depth, topology: record.topology,
+                                 lattice: record.lattice, neighborhood, mask: None};
+        // before anything the size of the board gets allocated
+        let area = size.checked_area().ok_or(BoardError::Overflow)?;
+        if record.cells.len() != area {
+            return Err(BoardError::InvalidSave(format!("{} cells for a board of {}", record.cells.len(), area)))
+        }
+        if let Some(mask) = record.mask {
+            size = size.with_mask(mask)?;
+        }
+        let mut board = Board::new_blank(size, record.mine_count, record.mines_per_cell)?;
+        for (cell, saved) in board.field.iter_mut().zip(record.cells) {
+            if saved.point != cell.point {
+                return Err(BoardError::InvalidSave(format!("{} is where {} should be", saved.point, cell.point)))
+            }
+            *cell = saved;
+        }
+        board.clue_noise = record.clue_noise;
+        board.first_click_policy = record.first_click_policy;
+        board.dealt_with = record.dealt_with;
+        board.seed = record.seed;
+        board.initialized = record.initialized;
+        board.checked()
+    }
+}
+
+// JSON for people and other programs, a compact binary form for everything else
+#[cfg(feature = "serde")]
+impl Board {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("boards always serialize")
+    }
+
+    pub fn from_json(text: &str) -> Result<Board, BoardError> {
+        serde_json::from_str(text).map_err(|error| BoardError::InvalidSave(error.to_string()))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        use bincode::Options;
+        bincode::DefaultOptions::new().serialize(self).expect("boards always serialize")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Board, BoardError> {
+        use bincode::Options;
+        // the limit keeps a made up length from allocating more than the input could hold
+        bincode::DefaultOptions::new()
+            .with_limit(bytes.len() as u64)
+            .reject_trailing_bytes()
+            .deserialize(bytes)
+            .map_err(|error| BoardError::InvalidSave(error.to_string()))
     }
 }
 
@@ -502,19 +1607,19 @@ mod cell_tests {
     use super::*;
 
     fn knowledge_states() -> [KnowledgeState; 3]{
-        [KnowledgeState::Unknown, KnowledgeState::Flag, KnowledgeState::Known]
+        [KnowledgeState::Unknown, KnowledgeState::Flag(1), KnowledgeState::Known]
     }
 
     #[test]
     fn toggle_flag_correctness() {
         for start_state in knowledge_states().iter() {
-            let mut cell = Cell::create_empty(Point(0, 0));
+            let mut cell = Cell::create_empty(Point(0, 0, 0));
             cell.knowledge = start_state.clone();
             cell.toggle_flag();
             match (start_state, cell.knowledge){
                 (KnowledgeState::Known, KnowledgeState::Known) => {},
-                (KnowledgeState::Flag, KnowledgeState::Unknown) => {},
-                (KnowledgeState::Unknown, KnowledgeState::Flag) => {},
+                (KnowledgeState::Flag(1), KnowledgeState::Unknown) => {},
+                (KnowledgeState::Unknown, KnowledgeState::Flag(1)) => {},
                 _ => panic!("got an unexpected toggle state")
             };
         }
@@ -524,6 +1629,7 @@ mod cell_tests {
 #[cfg(test)]
 mod board_tests {
     use super::*;
+    use itertools::Itertools;
 
     fn point_fits_on_board(point: &Point, board: &BoardSize) -> bool {
         point.0 < board.height && point.1 < board.width
@@ -532,7 +1638,7 @@ mod board_tests {
     fn valid_points_for_board(points: &[Point], board: &BoardSize) -> bool {
         // points should have length area() and every pair should appear once
         let points_count = points.len();
-        if points.iter().any(|point| !point_fits_on_board(point, &board)) {
+        if points.iter().any(|point| !point_fits_on_board(point, board)) {
             return false
         }
 
@@ -542,13 +1648,13 @@ mod board_tests {
     proptest! {
         #[test]
         fn area_correctness(width in 0..1000usize, height in 0..1000usize) {
-            prop_assert_eq!(BoardSize{width, height}.area(), width * height);
+            prop_assert_eq!(BoardSize::new(width, height).area(), width * height);
 
         }
 
         #[test]
         fn point_from_integer_correctness(x in any::<usize>(), width in 0..1000usize, height in 0..1000usize) {
-            let board = BoardSize{width, height};
+            let board = BoardSize::new(width, height);
             match board.point_from_integer(x) {
                 None => prop_assert!(x >= width * height),
                 Some(point) => {
@@ -560,7 +1666,7 @@ mod board_tests {
 
         #[test]
         fn test_points(width in 0..100usize, height in 0..100usize) {
-            let board = BoardSize{width, height};
+            let board = BoardSize::new(width, height);
             let points = board.points();
             let points_count = points.len();
             prop_assert_eq!(points_count, board.area());
@@ -569,7 +1675,7 @@ mod board_tests {
 
         #[test]
         fn distance_to_self_is_zero(x in any::<usize>(), y in any::<usize>()) {
-            let point = Point(x, y);
+            let point = Point(x, y, 0);
             prop_assert_eq!(point.distance(&point), 0);
             prop_assert_eq!(point, point);
         }
@@ -577,16 +1683,16 @@ mod board_tests {
         #[test]
         fn distance_is_symmetric(x1 in 0..1000usize, y1 in 0..1000usize,
                                  x2 in 0..1000usize, y2 in 0..1000usize) {
-            let point1 = Point(x1, y1);
-            let point2 = Point(x2, y2);
+            let point1 = Point(x1, y1, 0);
+            let point2 = Point(x2, y2, 0);
             prop_assert_eq!(point1.distance(&point2), point2.distance(&point1));
         }
 
         #[test]
         fn test_partial_eq(x1 in 0..1000usize, y1 in 0..1000usize,
                            x2 in 0..1000usize, y2 in 0..1000usize) {
-            let point1 = Point(x1, y1);
-            let point2 = Point(x2, y2);
+            let point1 = Point(x1, y1, 0);
+            let point2 = Point(x2, y2, 0);
             let distance = point1.distance(&point2);
             if point1 == point2 {
                 prop_assert_eq!(distance, 0)
@@ -599,9 +1705,12 @@ mod board_tests {
         fn test_sample_points(width in 0..100usize, height in 0..100usize,
                               x in 0..100usize, y in 0..100usize,
                               num_mines in 0..10000usize, disallowed_radius in 0..100usize) {
-            let boardsize = BoardSize{width, height};
-            let point = Point(x, y);
-            match sample_points(&boardsize, num_mines, &point, disallowed_radius){
+            let boardsize = BoardSize::new(width, height);
+            let point = Point(x, y, 0);
+            let avoid: HashSet<Point> = boardsize.points().into_iter()
+                .filter(|x| boardsize.distance(&point, x) <= disallowed_radius)
+                .collect();
+            match sample_points(&boardsize, num_mines, &avoid, 1, &mut ChaCha8Rng::from_entropy()){
                 None => {
                     let failure_conditions = point_fits_on_board(&point, &boardsize)
                         || boardsize.area() < (disallowed_radius*2+1).pow(2) + num_mines;
@@ -617,10 +1726,19 @@ mod board_tests {
         #[test]
         fn test_new_from_int(width in 0..100usize, height in 0..100usize, mine_count in 0..10000usize) {
             match Board::new_from_ints(width, height, mine_count) {
-                None => { 
+                Err(BoardError::ZeroDimensions) => {
+                    prop_assert_eq!(width * height, 0);
+                },
+                Err(BoardError::TooManyMinesForFirstClick{..}) => {
+                    // the luckiest first click is in a corner and keeps up to 3x3 cells clear
+                    prop_assert!(mine_count <= width * height);
+                    prop_assert!(mine_count > width * height - width.min(3) * height.min(3));
+                },
+                Err(error) => {
+                    prop_assert_eq!(error, BoardError::TooManyMines{mine_count, capacity: width * height});
                     prop_assert!(mine_count > width * height);
                 },
-                Some(board) => {
+                Ok(board) => {
                     prop_assert!(!board.initialized);
                     prop_assert_eq!(board.mine_count, mine_count);
                     prop_assert_eq!(board.size.width, width);
@@ -633,7 +1751,7 @@ mod board_tests {
         }
 
         #[test]
-        fn test_retrieve_cell(width in 0..100usize, height in 0..100usize) {
+        fn test_retrieve_cell(width in 1..100usize, height in 1..100usize) {
             let board = Board::new_from_ints(width, height, 0).unwrap();
             let points: Vec<Point> = board.cells().into_iter().map(|c| c.point).collect();
             for point in points {
@@ -658,17 +1776,15 @@ mod board_tests {
         #[test]
         fn test_found_mines_and_remaining_mines(width in 1..20usize, height in 1..20usize) {
             let mine_count = 1;
-            let mut board = Board::new_from_ints(width, height, mine_count).unwrap();
+            let mut board = Board::new_with_first_click_policy(BoardSize::new(width, height), mine_count, 1, FirstClickPolicy::Unprotected).unwrap();
             let mut mine_count = mine_count as i32;
             let points: Vec<Point> = board.cells().into_iter().map(|c| c.point).collect();
-            let mut flagged_points = 0;
-            prop_assert_eq!(board.found_mines(), flagged_points);
+            prop_assert_eq!(board.found_mines(), 0);
             prop_assert_eq!(board.remaining_mines(), mine_count);
-            for point in points {
-                board.retrieve_cell_mutable(&point).knowledge = KnowledgeState::Flag;
-                flagged_points += 1;
+            for (i, point) in points.into_iter().enumerate() {
+                board.retrieve_cell_mutable(&point).knowledge = KnowledgeState::Flag(1);
                 mine_count -= 1;
-                prop_assert_eq!(board.found_mines(), flagged_points);
+                prop_assert_eq!(board.found_mines(), i + 1);
                 prop_assert_eq!(board.remaining_mines(), mine_count);
             }
         }
@@ -676,14 +1792,459 @@ mod board_tests {
         #[test]
         fn test_neighbor_methods(width in 1..20usize, height in 1..20usize) {
             let mine_count = 1;
-            let board = Board::new_from_ints(width, height, mine_count).unwrap();
+            let board = Board::new_with_first_click_policy(BoardSize::new(width, height), mine_count, 1, FirstClickPolicy::Unprotected).unwrap();
             let points: Vec<Point> = board.size.points();
             for point in points {
                 let all_distance_one = board.neighbor_points(&point).iter()
-                    .all(|neighbor| (point.distance(neighbor) == 1));
+                    .all(|neighbor| point.distance(neighbor) == 1);
                 prop_assert!(all_distance_one);
             }
         }
 
+
+        #[test]
+        fn torus_neighbors_wrap(width in 1..12usize, height in 1..12usize) {
+            let size = BoardSize::with_topology(width, height, Topology::Torus);
+            for point in size.points() {
+                let neighbors = size.neighbor_points(&point);
+                if width >= 3 && height >= 3 {
+                    prop_assert_eq!(neighbors.len(), 8);
+                }
+                for neighbor in neighbors {
+                    prop_assert!(size.point_is_in_bounds(&neighbor));
+                    prop_assert_eq!(size.distance(&point, &neighbor), 1);
+                    prop_assert!(size.neighbor_points(&neighbor).contains(&point));
+                }
+            }
+        }
+
+        #[test]
+        fn torus_mine_counts(width in 3..10usize, height in 3..10usize, seed in 0..1000usize) {
+            let size = BoardSize::with_topology(width, height, Topology::Torus);
+            let mines: Vec<Point> = size.points().into_iter().filter(|p| (p.0 * 31 + p.1 * 17 + seed) % 5 == 0).collect();
+            let board = Board::new_with_mines(size, &mines).unwrap();
+            for point in board.size.points() {
+                let expected = board.neighbor_points(&point).iter().filter(|n| mines.contains(n)).count();
+                prop_assert_eq!(board.retrieve_cell(&point).mined_neighbor_count, expected);
+            }
+        }
+
+
+        #[test]
+        fn hex_neighbors(width in 1..12usize, height in 1..12usize, torus in any::<bool>()) {
+            let topology = if torus { Topology::Torus } else { Topology::Rectangle };
+            let size = match BoardSize::with_lattice(width, height, Lattice::Hex, topology) {
+                Ok(size) => size,
+                Err(error) => {
+                    prop_assert!(torus && height % 2 == 1);
+                    prop_assert_eq!(error, BoardError::OddHexTorus{height});
+                    return Ok(())
+                }
+            };
+            for point in size.points() {
+                let neighbors = size.neighbor_points(&point);
+                let interior = point.0 > 0 && point.0 + 1 < height && point.1 > 0 && point.1 + 1 < width;
+                if interior || (torus && width >= 3 && height >= 4) {
+                    prop_assert_eq!(neighbors.len(), 6);
+                }
+                for neighbor in neighbors {
+                    prop_assert_eq!(size.distance(&point, &neighbor), 1);
+                    prop_assert!(size.neighbor_points(&neighbor).contains(&point));
+                }
+            }
+        }
+
+        #[test]
+        fn points_within_the_box(width in 1..9usize, height in 1..9usize, depth in 1..3usize, shape in 0..4usize,
+                                 x in 0..9usize, y in 0..9usize, radius in 0..6usize) {
+            let topology = if shape % 2 == 0 { Topology::Rectangle } else { Topology::Torus };
+            let size = if shape < 2 {
+                BoardSize::with_depth(width, height, depth, topology)
+            } else {
+                BoardSize::with_lattice(width, height + height % 2, Lattice::Hex, topology).unwrap()
+            };
+            let point = Point(x % size.height(), y % width, 0);
+            let scanned: HashSet<Point> = size.points().into_iter().filter(|other| size.distance(&point, other) <= radius).collect();
+            prop_assert_eq!(size.points_within(&point, radius), scanned);
+        }
+
+        #[test]
+        fn fewest_within_matches_a_scan(width in 1..7usize, height in 1..7usize, depth in 1..3usize, shape in 0..6usize,
+                                        radius in 0..16usize, seed in 0..1000usize) {
+            let topology = if shape % 2 == 0 { Topology::Rectangle } else { Topology::Torus };
+            let size = match shape {
+                0 | 1 => BoardSize::with_depth(width, height, depth, topology),
+                2 | 3 => BoardSize::with_lattice(width, height + height % 2, Lattice::Hex, topology).unwrap(),
+                _ => {
+                    let mut mask: Vec<bool> = (0..width * height).map(|x| (x * 7 + seed) % 3 != 0).collect();
+                    let kept = seed % mask.len();
+                    mask[kept] = true;
+                    BoardSize::with_topology(width, height, topology).with_mask(mask).unwrap()
+                }
+            };
+            let scanned = size.points().iter().map(|point| size.points_within(point, radius).len()).min().unwrap();
+            prop_assert_eq!(size.fewest_within(radius), scanned);
+        }
+
+        #[test]
+        fn hex_distance_is_symmetric(x1 in 0..50usize, y1 in 0..50usize, x2 in 0..50usize, y2 in 0..50usize) {
+            let size = BoardSize::with_lattice(50, 50, Lattice::Hex, Topology::Rectangle).unwrap();
+            let (a, b) = (Point(x1, y1, 0), Point(x2, y2, 0));
+            prop_assert_eq!(size.distance(&a, &b), size.distance(&b, &a));
+            prop_assert_eq!(size.distance(&a, &b) == 0, a == b);
+        }
+
+
+        #[test]
+        fn custom_neighborhood_counts(width in 1..12usize, height in 1..12usize, preset in 0..4usize, seed in 0..1000usize) {
+            let neighborhood = [Neighborhood::orthogonal(), Neighborhood::radius(2), Neighborhood::knight(),
+                                Neighborhood::from_offsets(&[(0, 3)]).unwrap()][preset].clone();
+            let expected_interior = [4, 24, 8, 2][preset];
+            let size = BoardSize::with_neighborhood(width, height, neighborhood, Topology::Rectangle);
+            let mines: Vec<Point> = size.points().into_iter().filter(|p| (p.0 * 31 + p.1 * 17 + seed) % 4 == 0).collect();
+            let board = Board::new_with_mines(size, &mines).unwrap();
+            for point in board.size.points() {
+                let neighbors = board.neighbor_points(&point);
+                if point.0 >= 3 && point.0 + 3 < height && point.1 >= 3 && point.1 + 3 < width {
+                    prop_assert_eq!(neighbors.len(), expected_interior);
+                }
+                for neighbor in &neighbors {
+                    prop_assert!(board.neighbor_points(neighbor).contains(&point));
+                }
+                let expected = neighbors.iter().filter(|n| mines.contains(n)).count();
+                prop_assert_eq!(board.retrieve_cell(&point).mined_neighbor_count, expected);
+            }
+        }
+
+        #[test]
+        fn layered_points_round_trip(width in 1..10usize, height in 1..10usize, depth in 1..10usize) {
+            let size = BoardSize::with_depth(width, height, depth, Topology::Rectangle);
+            let points = size.points();
+            prop_assert_eq!(points.len(), width * height * depth);
+            for (i, point) in points.iter().enumerate() {
+                prop_assert_eq!(size.integer_from_point(point), Some(i));
+            }
+            prop_assert!(!size.point_is_in_bounds(&Point(0, 0, depth)));
+        }
+
+        #[test]
+        fn layered_neighbors(width in 1..8usize, height in 1..8usize, depth in 1..8usize, torus in any::<bool>()) {
+            let topology = if torus { Topology::Torus } else { Topology::Rectangle };
+            let size = BoardSize::with_depth(width, height, depth, topology);
+            for point in size.points() {
+                let neighbors = size.neighbor_points(&point);
+                let interior = [(point.0, height), (point.1, width), (point.2, depth)].iter()
+                    .all(|(x, size)| *x > 0 && x + 1 < *size);
+                if interior || (torus && width >= 3 && height >= 3 && depth >= 3) {
+                    prop_assert_eq!(neighbors.len(), 26);
+                }
+                for neighbor in neighbors {
+                    prop_assert_eq!(size.distance(&point, &neighbor), 1);
+                    prop_assert!(size.neighbor_points(&neighbor).contains(&point));
+                }
+            }
+        }
+
+
+        #[test]
+        fn masked_cells_are_skipped(width in 1..12usize, height in 1..12usize, seed in 0..1000usize, mine_count in 0..20usize) {
+            let mask: Vec<bool> = (0..width * height).map(|i| (i * 7 + seed) % 3 != 0).collect();
+            let size = BoardSize::new(width, height).with_mask(mask.clone()).unwrap();
+            let points = size.points();
+            prop_assert_eq!(points.len(), mask.iter().filter(|playable| **playable).count());
+            prop_assert_eq!(points.len(), size.playable_area());
+            for point in &points {
+                prop_assert!(mask[size.integer_from_point(point).unwrap()]);
+                prop_assert!(size.neighbor_points(point).iter().all(|neighbor| size.is_playable(neighbor)));
+            }
+            // the opening click keeps a 5x5 square clear
+            let mine_count = mine_count.min(points.len().saturating_sub(25));
+            if let (Ok(mut board), Some(first)) = (Board::new_from_size(size, mine_count), points.first()) {
+                board.probe(first);
+                for point in BoardSize::new(width, height).points() {
+                    if !board.size.is_playable(&point) {
+                        prop_assert!(matches!(board.retrieve_cell(&point).content, Content::Empty));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn multi_mine_counts(width in 1..10usize, height in 1..10usize, seed in 0..1000usize) {
+            let size = BoardSize::new(width, height);
+            let mines: Vec<Point> = size.points().into_iter()
+                .flat_map(|p| std::iter::repeat_n(p, (p.0 * 31 + p.1 * 17 + seed) % 4))
+                .collect();
+            let mut board = Board::new_with_mines(size, &mines).unwrap();
+            prop_assert_eq!(board.mine_count, mines.len());
+            for point in board.size.points() {
+                let expected = board.neighbor_points(&point).iter()
+                    .map(|n| mines.iter().filter(|m| *m == n).count())
+                    .sum::<usize>();
+                prop_assert_eq!(board.retrieve_cell(&point).mined_neighbor_count, expected);
+            }
+            let point = Point(0, 0, 0);
+            for flags in 1..board.mines_per_cell() + 1 {
+                board.toggle_flag(&point);
+                prop_assert_eq!(board.found_mines(), flags);
+            }
+            board.toggle_flag(&point);
+            prop_assert!(board.retrieve_cell(&point).knowledge.is_unknown());
+        }
+
+        #[test]
+        fn noisy_clues_stay_close(width in 1..10usize, height in 1..10usize, seed in 0..1000usize, noise in 0..4usize) {
+            let size = BoardSize::new(width, height);
+            let mines: Vec<Point> = size.points().into_iter().filter(|p| (p.0 * 31 + p.1 * 17 + seed) % 4 == 0).collect();
+            let clue_noise = ClueNoise::OffByOne(noise as f64 / 3.0);
+            let board = Board::new_with_mines(size, &mines).unwrap().with_clue_noise(clue_noise).unwrap();
+            for point in board.size.points() {
+                let cell = board.retrieve_cell(&point);
+                prop_assert!(clue_noise.likelihood(cell.clue, cell.mined_neighbor_count) > 0.0);
+                let total: f64 = (0..cell.mined_neighbor_count + 3).map(|clue| clue_noise.likelihood(clue, cell.mined_neighbor_count)).sum();
+                prop_assert!((total - 1.0).abs() < 1e-9);
+            }
+        }
+
+        #[test]
+        fn first_click_policies(width in 1..12usize, height in 1..12usize, x in 0..12usize, y in 0..12usize,
+                                mine_count in 0..150usize, policy in 0..5usize, radius in 0..4usize) {
+            let policy = [FirstClickPolicy::Unprotected, FirstClickPolicy::SafeCell, FirstClickPolicy::SafeOpening,
+                          FirstClickPolicy::SafeRadius(radius), FirstClickPolicy::WindowsClassic][policy];
+            let point = Point(x % height, y % width, 0);
+            let board = Board::new_with_first_click_policy(BoardSize::new(width, height), mine_count, 1, policy);
+            if let Ok(mut board) = board {
+                board.probe(&point);
+                let mined = |p: &Point| matches!(board.retrieve_cell(p).content, Content::Mine(_));
+                let placed = board.size.points().iter().filter(|p| mined(p)).count();
+                prop_assert_eq!(placed, mine_count);
+                let opening = board.neighbor_points(&point).len() + 1;
+                let safe = match policy {
+                    FirstClickPolicy::Unprotected => false,
+                    FirstClickPolicy::SafeOpening => mine_count + opening <= width * height,
+                    _ => mine_count < width * height
+                };
+                if safe {
+                    prop_assert!(!mined(&point));
+                }
+                if policy == FirstClickPolicy::SafeOpening && safe {
+                    prop_assert!(board.neighbor_points(&point).iter().all(|p| !mined(p)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn board_from_pattern() {
+        let size = BoardSize::from_pattern(".. ..\n.....\n .", Topology::Rectangle).unwrap();
+        assert_eq!((size.width(), size.height()), (5, 3));
+        assert_eq!(size.playable_area(), 10);
+        assert!(!size.is_playable(&Point(0, 2, 0)));
+        assert!(!size.is_playable(&Point(2, 4, 0)));
+        assert_eq!(size.neighbor_points(&Point(0, 1, 0)).len(), 4);
+        assert_eq!(BoardSize::new(3, 3).with_mask(vec![true; 8]).err(), Some(BoardError::MaskSize{expected: 9, found: 8}));
+
+        let board = Board::new_with_mines(size, &[Point(1, 2, 0)]).unwrap();
+        let masked = Board::new_with_mines(BoardSize::from_pattern(". .", Topology::Rectangle).unwrap(), &[Point(0, 1, 0)]);
+        assert_eq!(masked.err(), Some(BoardError::OutOfBounds(Point(0, 1, 0))));
+        assert_eq!(board.to_string(), "  01234\n0 □□ □□\n1 □□□□□\n2  □   \n");
+    }
+
+    #[test]
+    fn board_errors() {
+        assert_eq!(Board::new_from_ints(0, 5, 0).err(), Some(BoardError::ZeroDimensions));
+        assert_eq!(Board::new_from_ints(usize::MAX, 2, 0).err(), Some(BoardError::Overflow));
+        assert_eq!(Board::new_with_mines_per_cell(BoardSize::new(3, 3), 10, 1).err(),
+                   Some(BoardError::TooManyMines{mine_count: 10, capacity: 9}));
+        // every constructor checks the default policy, nothing within 2 of the first click
+        assert_eq!(Board::new_from_ints(3, 3, 9).err(),
+                   Some(BoardError::TooManyMinesForFirstClick{mine_count: 9, policy: FirstClickPolicy::SafeRadius(2)}));
+        assert!(Board::new_from_ints(3, 3, 1).is_err());
+        assert!(Board::new_from_ints(5, 5, 16).is_ok());
+        assert!(Board::new_from_ints(5, 5, 17).is_err());
+        let policy = |mine_count: usize, policy: FirstClickPolicy| Board::new_with_first_click_policy(BoardSize::new(3, 3), mine_count, 1, policy);
+        assert!(policy(9, FirstClickPolicy::Unprotected).is_ok());
+        assert_eq!(policy(9, FirstClickPolicy::SafeCell).err(),
+                   Some(BoardError::TooManyMinesForFirstClick{mine_count: 9, policy: FirstClickPolicy::SafeCell}));
+        // a click in the corner only keeps four cells clear
+        let policy = |mine_count: usize| Board::new_with_first_click_policy(BoardSize::new(5, 5), mine_count, 1, FirstClickPolicy::SafeRadius(1));
+        assert!(policy(21).is_ok());
+        assert!(policy(22).is_err());
+
+        // a radius wider than the board is counted, not scanned for every cell
+        let started = std::time::Instant::now();
+        let huge = FirstClickPolicy::SafeRadius(1000000);
+        let dense = Board::new_with_first_click_policy(BoardSize::new(300, 300), 89999, 1, FirstClickPolicy::Unprotected).unwrap();
+        assert!(dense.clone().with_first_click_policy(huge).is_err());
+        let saved = dense.to_save_string().replace("first-click unprotected", "first-click safe-radius 1000000");
+        assert!(Board::from_save_string(&saved).is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        // offsets that couldn't be turned around or added to a cell are turned down
+        assert_eq!(Neighborhood::from_offsets(&[(1, 0), (i64::MIN, 0)]).err(), Some(BoardError::OffsetTooFar{row: i64::MIN, column: 0}));
+        assert_eq!(Neighborhood::from_offsets(&[(0, i64::MAX)]).err(), Some(BoardError::OffsetTooFar{row: 0, column: i64::MAX}));
+        assert_eq!(Neighborhood::from_offsets(&[(0, 1 << 32)]).unwrap().offsets(), &[(0, 1 << 32), (0, -(1 << 32))]);
+    }
+
+    #[test]
+    fn difficulties() {
+        for (name, difficulty) in [("beginner", Difficulty::Beginner), ("Intermediate", Difficulty::Intermediate),
+                                   ("EXPERT", Difficulty::Expert)].iter() {
+            assert_eq!(Difficulty::from_name(name), Some(*difficulty));
+            let board = Board::new_from_difficulty(*difficulty).unwrap();
+            assert_eq!((board.size.width(), board.size.height(), board.mine_count),
+                       (difficulty.width(), difficulty.height(), difficulty.mine_count()));
+        }
+        let custom = Difficulty::Custom{width: 20, height: 10, mine_count: 35};
+        assert_eq!(Difficulty::from_name("20x10/35"), Some(custom));
+        assert_eq!(Difficulty::from_name(&custom.to_string()), Some(custom));
+        assert_eq!(Difficulty::from_name("20x10"), None);
+        assert_eq!(Board::new_with_density(BoardSize::new(30, 16), 0.206).unwrap().mine_count, 99);
+        assert_eq!(Board::new_with_density(BoardSize::new(9, 9), 1.5).err(), Some(BoardError::InvalidDensity));
+        for p in [-0.1, 1.5, f64::NAN].iter() {
+            let noisy = Board::new_from_ints(9, 9, 10).unwrap().with_clue_noise(ClueNoise::OffByOne(*p));
+            assert_eq!(noisy.err(), Some(BoardError::InvalidNoise));
+        }
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let size = BoardSize::from_pattern("....\n.. .\n....", Topology::Torus).unwrap();
+        let mut board = Board::new_with_first_click_policy(size, 4, 2, FirstClickPolicy::SafeCell).unwrap()
+            .with_clue_noise(ClueNoise::OffByOne(0.25)).unwrap();
+        board.probe(&Point(0, 0, 0));
+        let flag = board.get_unknown_points()[0];
+        board.toggle_flag(&flag);
+        let saved = board.to_save_string();
+        let loaded = Board::from_save_string(&saved).unwrap();
+        assert_eq!(loaded.to_save_string(), saved);
+        assert_eq!(loaded.to_string(), board.to_string());
+        assert_eq!(loaded.remaining_mines(), board.remaining_mines());
+        assert!(!loaded.size.is_playable(&Point(1, 2, 0)));
+
+        let knight = BoardSize::with_neighborhood(5, 5, Neighborhood::knight(), Topology::Rectangle);
+        let fresh = Board::new_from_size(knight, 3).unwrap();
+        let loaded = Board::from_save_string(&fresh.to_save_string()).unwrap();
+        assert_eq!(loaded.size.neighborhood(), Some(&Neighborhood::knight()));
+        assert!(!loaded.initialized);
+        // offsets from a save are bounded before anything adds them to coordinates
+        let knight_line = "neighborhood -2,-1 2,1 -2,1 2,-1 -1,-2 1,2 -1,2 1,-2";
+        let saved_knight = fresh.to_save_string();
+        assert!(saved_knight.contains(knight_line));
+        for offsets in [String::from("-9223372036854775808,0"), String::from("0,9223372036854775807"),
+                        vec!["1,1"; MAX_NEIGHBORHOOD + 1].join(" ")].iter() {
+            let edited = saved_knight.replace(knight_line, &format!("neighborhood {}", offsets));
+            assert!(matches!(Board::from_save_string(&edited), Err(BoardError::InvalidSave(_))));
+        }
+
+        assert!(matches!(Board::from_save_string("hello"), Err(BoardError::InvalidSave(_))));
+        let truncated: String = saved.lines().take(12).collect::<Vec<&str>>().join("\n");
+        assert!(matches!(Board::from_save_string(&truncated), Err(BoardError::InvalidSave(_))));
+    }
+
+    #[test]
+    fn fallbacks_go_one_step_and_are_recorded() {
+        let mut chain = vec![];
+        let mut policy = Some(FirstClickPolicy::SafeRadius(3));
+        while let Some(weaker) = policy.and_then(|policy| policy.weaker()) {
+            chain.push(weaker);
+            policy = Some(weaker);
+        }
+        assert_eq!(chain, vec![FirstClickPolicy::SafeRadius(2), FirstClickPolicy::SafeRadius(1), FirstClickPolicy::SafeOpening,
+                               FirstClickPolicy::SafeCell, FirstClickPolicy::Unprotected]);
+        assert_eq!(FirstClickPolicy::SafeRadius(0).weaker(), Some(FirstClickPolicy::SafeCell));
+
+        // 16 mines fit around a corner click but not around one in the middle
+        let mut board = Board::new_from_ints(5, 5, 16).unwrap();
+        assert_eq!(board.dealt_with(), None);
+        board.probe(&Point(2, 2, 0));
+        assert_eq!(board.dealt_with(), Some(FirstClickPolicy::SafeRadius(1)));
+        let loaded = Board::from_save_string(&board.to_save_string()).unwrap();
+        assert_eq!(loaded.dealt_with(), Some(FirstClickPolicy::SafeRadius(1)));
+        assert_eq!(board.new_game().dealt_with(), None);
+    }
+
+    #[test]
+    fn new_game_keeps_the_settings() {
+        let mut board = Board::new_from_ints(6, 6, 5).unwrap()
+            .with_first_click_policy(FirstClickPolicy::SafeCell).unwrap();
+        board.probe(&Point(2, 2, 0));
+        let fresh = board.new_game();
+        assert!(!fresh.initialized);
+        assert_eq!(fresh.unknown_count(), 36);
+        assert_eq!((fresh.mine_count, fresh.first_click_policy()), (5, FirstClickPolicy::SafeCell));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let size = BoardSize::from_pattern("....\n.. .\n....", Topology::Torus).unwrap();
+        let mut board = Board::new_with_first_click_policy(size, 4, 2, FirstClickPolicy::SafeCell).unwrap()
+            .with_clue_noise(ClueNoise::OffByOne(0.25)).unwrap()
+            .with_seed(9);
+        board.probe(&Point(0, 0, 0));
+        let flag = board.get_unknown_points()[0];
+        board.toggle_flag(&flag);
+
+        let json = board.to_json();
+        assert_eq!(Board::from_json(&json).unwrap().to_save_string(), board.to_save_string());
+        let bytes = board.to_bytes();
+        assert!(bytes.len() < json.len() / 4);
+        assert_eq!(Board::from_bytes(&bytes).unwrap().to_save_string(), board.to_save_string());
+        assert!(Board::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let action = crate::ActionType::Flag(Point(1, 2, 0));
+        assert_eq!(serde_json::from_str::<crate::ActionType>(&serde_json::to_string(&action).unwrap()).unwrap(), action);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserializing_checks_the_board() {
+        let mut board = Board::new_with_mines(BoardSize::new(3, 3), &[Point(0, 0, 0)]).unwrap();
+        board.probe(&Point(2, 2, 0));
+        let json: serde_json::Value = serde_json::from_str(&board.to_json()).unwrap();
+        let edited = |edit: &dyn Fn(&mut serde_json::Value)| {
+            let mut json = json.clone();
+            edit(&mut json);
+            Board::from_json(&json.to_string())
+        };
+
+        // neighbour counts are worked out again, whatever they say
+        let recounted = edited(&|json| json["cells"][4]["mined_neighbor_count"] = 7.into()).unwrap();
+        assert_eq!(recounted.retrieve_cell(&Point(1, 1, 0)).mined_neighbor_count, 1);
+        let left_out = edited(&|json| { json["cells"][4].as_object_mut().unwrap().remove("mined_neighbor_count"); }).unwrap();
+        assert_eq!(left_out.retrieve_cell(&Point(1, 1, 0)).mined_neighbor_count, 1);
+
+        assert!(edited(&|json| json["cells"][4]["clue"] = 3.into()).is_err());
+        assert!(edited(&|json| json["cells"][8]["content"] = serde_json::json!({"Mine": 1})).is_err());
+        assert!(edited(&|json| json["cells"][0]["content"] = serde_json::json!({"Mine": 2})).is_err());
+        assert!(edited(&|json| json["cells"][1]["point"] = serde_json::json!([2, 2, 0])).is_err());
+        assert!(edited(&|json| { json["cells"].as_array_mut().unwrap().pop(); }).is_err());
+        assert!(edited(&|json| json["width"] = 1000000000.into()).is_err());
+        assert!(edited(&|json| json["initialized"] = false.into()).is_err());
+        assert!(edited(&|json| json["neighborhood"] = serde_json::json!([[i64::MIN, 0]])).is_err());
+    }
+
+    #[test]
+    fn seeds_repeat_the_deal() {
+        let deal = |seed: u64| {
+            let mut board = Board::new_from_ints(8, 8, 10).unwrap().with_seed(seed);
+            board.probe(&Point(3, 3, 0));
+            board.to_save_string()
+        };
+        assert_eq!(deal(7), deal(7));
+        assert_ne!(deal(7), deal(8));
+
+        // pinned, a seed has to deal these mines on every platform and rand version
+        let mut board = Board::new_from_ints(8, 8, 10).unwrap().with_seed(7);
+        board.probe(&Point(3, 3, 0));
+        let mines: Vec<Point> = board.size.points().into_iter()
+            .filter(|point| matches!(board.retrieve_cell(point).content, Content::Mine(_)))
+            .collect();
+        assert_eq!(mines, vec![Point(2, 7, 0), Point(3, 6, 0), Point(3, 7, 0), Point(4, 7, 0), Point(5, 6, 0),
+                              Point(5, 7, 0), Point(6, 0, 0), Point(6, 1, 0), Point(6, 5, 0), Point(7, 6, 0)]);
+        let saved = Board::new_from_ints(8, 8, 10).unwrap().with_seed(7).to_save_string();
+        assert_eq!(Board::from_save_string(&saved).unwrap().seed(), Some(7));
+        assert_eq!(Board::from_save_string(&saved).unwrap().new_game().seed(), None);
     }
 }
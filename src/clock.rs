@@ -0,0 +1,5 @@
+// std's Instant panics on wasm32-unknown-unknown, the browser has its own clock
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub use web_time::Instant;
@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::hash::Hash;
-use std::rc::Rc;
 use std::fmt::Debug;
 use std::sync::Arc;
+use std::sync::OnceLock;
+use rayon::prelude::*;
+use super::clock::Instant;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Variable<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> 
 {
     pub id: S,
@@ -20,6 +22,7 @@ pub trait SelectionStrategy<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash
                       points: &[S], available_indices: &HashSet<usize>) -> Option<usize>;
 }
 
+#[derive(Clone, Copy)]
 pub struct RandomSelectionStrategy { }
 
 impl<S, T> SelectionStrategy<S, T> for RandomSelectionStrategy where
@@ -29,13 +32,11 @@ impl<S, T> SelectionStrategy<S, T> for RandomSelectionStrategy where
     fn get_next_index(&self, _: &HashMap<S, Variable<S, T>>,
                       _: &HashMap<S, Vec<Arc<dyn Constraint<S, T>  + Send + Sync>>>,
                       _: &[S], available_indices: &HashSet<usize>) -> Option<usize> {
-        match available_indices.iter().next() {
-            None => None,
-            Some(&val) => Some(val)
-        }
+        available_indices.iter().next().copied()
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct DegreeSelectionStrategy { }
 
 impl<S, T> SelectionStrategy<S, T> for DegreeSelectionStrategy where
@@ -54,10 +55,7 @@ impl<S, T> SelectionStrategy<S, T> for DegreeSelectionStrategy where
                 (count, idx)
             })
             .max();
-        match result {
-            None => None,
-            Some((_, idx)) => Some(*idx)
-        }
+        result.map(|(_, idx)| *idx)
     }
 }
 
@@ -68,8 +66,25 @@ pub trait Constraint<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq>
     fn check_constraint(&self, global_counts: &HashMap<T, usize>,
                         variable_lookup: &HashMap<S, Variable<S,T>>)-> bool;
     fn consistent_states_for_variable(&self, variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S) -> Vec<T>;
+
+    // Soft constraints only enforce a relaxed version of themselves above and say here
+    // how plausible a full solution is. Hard constraints leave every solution at 1
+    fn weight(&self, _solution: &HashMap<S, T>) -> f64 {
+        1.0
+    }
+}
+
+// Anything that can produce a full assignment of the variables it was built with
+pub trait Solver<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> {
+    fn solve(&mut self) -> Option<HashMap<S, T>>;
+
+    // how much a solution counts when sampling, see Constraint::weight
+    fn weight(&self, _solution: &HashMap<S, T>) -> f64 {
+        1.0
+    }
 }
 
+#[derive(Clone)]
 pub struct ConstraintSolver< S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq, Strat: SelectionStrategy<S, T>> 
 {
     pub variable_lookup: HashMap<S, Variable<S, T>>,
@@ -83,28 +98,67 @@ impl<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq, Strat: SelectionS
     pub fn new(variables: Vec<Variable<S, T>>,
                constraints: Vec<Arc<dyn Constraint<S, T>  + Send + Sync>>,
                selection_strategy: Strat) -> ConstraintSolver<S, T, Strat>{
-        let mut variable_to_constraints:HashMap<S, Vec<Arc<dyn Constraint<S, T>  + Send + Sync>>> = HashMap::with_capacity(constraints.len());
-        constraints.iter().for_each(|constraint| {
-            constraint.get_constrained_variable_ids().iter().for_each( |v_id| {
-                let group = variable_to_constraints.entry(*v_id).or_insert_with(|| vec![]);
-                group.push(Arc::clone(constraint)) // i am baffled that group doesn't have to be mut?
-            });
-        });
+        let variable_to_constraints = HashMap::with_capacity(constraints.len());
         let global_counts = HashMap::with_capacity(2);
 
         let variable_lookup = variables.into_iter()
             .map(|v| (v.id, v))
             .collect();
 
-        ConstraintSolver{variable_lookup, variable_to_constraints, global_counts, selection_strategy}
+        let mut solver = ConstraintSolver{variable_lookup, variable_to_constraints, global_counts, selection_strategy};
+        constraints.into_iter().for_each(|constraint| solver.add_constraint(constraint));
+        solver
+    }
+
+    pub fn add_variable(&mut self, variable: Variable<S, T>) {
+        self.remove_variable(&variable.id);
+        self.variable_lookup.insert(variable.id, variable);
+    }
+
+    // Constraints on the variable are left in place, remove them first
+    pub fn remove_variable(&mut self, v_id: &S) -> Option<Variable<S, T>> {
+        if self.variable_lookup.contains_key(v_id) {
+            self.set_variable_state(v_id, None);
+        }
+        self.variable_lookup.remove(v_id)
+    }
+
+    pub fn add_constraint(&mut self, constraint: Arc<dyn Constraint<S, T>  + Send + Sync>) {
+        constraint.get_constrained_variable_ids().iter().for_each( |v_id| {
+            let group = self.variable_to_constraints.entry(*v_id).or_default();
+            group.push(Arc::clone(&constraint))
+        });
+    }
+
+    pub fn remove_constraint(&mut self, constraint: &Arc<dyn Constraint<S, T>  + Send + Sync>) {
+        constraint.get_constrained_variable_ids().iter().for_each( |v_id| {
+            if let Some(group) = self.variable_to_constraints.get_mut(v_id) {
+                group.retain(|other| !Arc::ptr_eq(other, constraint));
+                if group.is_empty() {
+                    self.variable_to_constraints.remove(v_id);
+                }
+            }
+        });
     }
 
     pub fn backtrack(&mut self) -> Option<HashMap<S, T>>{
+        // values left over from the previous solution would constrain this one
         let remaining_points: Vec<S> = self.variable_lookup.keys().copied().collect();
+        remaining_points.iter().for_each(|v_id| self.set_variable_state(v_id, None));
         let mut indices: HashSet<usize> = (0..remaining_points.len()).collect();
         self._backtrack(&remaining_points, &mut indices)
     }
 
+    // the product of every constraint's weight
+    pub fn weight(&self, solution: &HashMap<S, T>) -> f64 {
+        let mut seen = HashSet::new();
+        self.variable_to_constraints.values()
+            .flatten()
+            .filter(|constraint| seen.insert(Arc::as_ptr(constraint) as *const () as usize))
+            .map(|constraint| constraint.weight(solution))
+            .product()
+    }
+
     fn set_variable_state(&mut self, v_id: &S, state: Option<T>){
         let var = self.variable_lookup.get_mut(v_id).expect("variable lookup can't find variable");
         if let Some(state) = var.value {
@@ -169,3 +223,651 @@ impl<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq, Strat: SelectionS
         }
     }
 }
+
+impl<S, T, Strat> Solver<S, T> for ConstraintSolver<S, T, Strat> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq,
+    Strat: SelectionStrategy<S, T>
+{
+    fn solve(&mut self) -> Option<HashMap<S, T>> {
+        self.backtrack()
+    }
+
+    fn weight(&self, solution: &HashMap<S, T>) -> f64 {
+        ConstraintSolver::weight(self, solution)
+    }
+}
+
+// How often each variable took each value over a set of solutions. Solutions can
+// carry a weight, frequencies are over the total weight rather than the count
+#[derive(Debug, Clone)]
+pub struct SolutionCounts<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> {
+    pub solutions: usize,
+    pub counts: HashMap<S, HashMap<T, usize>>,
+    pub total_weight: f64,
+    weights: HashMap<S, HashMap<T, f64>>
+}
+
+impl<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> SolutionCounts<S, T> {
+    pub fn new() -> SolutionCounts<S, T> {
+        SolutionCounts{solutions: 0, counts: HashMap::new(), total_weight: 0.0, weights: HashMap::new()}
+    }
+
+    pub fn add_solution(&mut self, solution: &HashMap<S, T>) {
+        self.add_weighted_solution(solution, 1.0);
+    }
+
+    pub fn add_weighted_solution(&mut self, solution: &HashMap<S, T>, weight: f64) {
+        self.solutions += 1;
+        self.total_weight += weight;
+        for (v_id, value) in solution {
+            *self.counts.entry(*v_id).or_default().entry(*value).or_insert(0) += 1;
+            *self.weights.entry(*v_id).or_default().entry(*value).or_insert(0.0) += weight;
+        }
+    }
+
+    pub fn merge(mut self, other: SolutionCounts<S, T>) -> SolutionCounts<S, T> {
+        self.solutions += other.solutions;
+        self.total_weight += other.total_weight;
+        for (v_id, values) in other.counts {
+            let mine = self.counts.entry(v_id).or_default();
+            for (value, count) in values {
+                *mine.entry(value).or_insert(0) += count;
+            }
+        }
+        for (v_id, values) in other.weights {
+            let mine = self.weights.entry(v_id).or_default();
+            for (value, weight) in values {
+                *mine.entry(value).or_insert(0.0) += weight;
+            }
+        }
+        self
+    }
+
+    pub fn count(&self, v_id: &S, value: &T) -> usize {
+        self.counts.get(v_id).and_then(|values| values.get(value)).copied().unwrap_or(0)
+    }
+
+    pub fn frequency(&self, v_id: &S, value: &T) -> f32 {
+        if self.total_weight <= 0.0 {
+            return 0.0
+        }
+        let weight = self.weights.get(v_id).and_then(|values| values.get(value)).copied().unwrap_or(0.0);
+        (weight / self.total_weight) as f32
+    }
+}
+
+impl<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> Default for SolutionCounts<S, T> {
+    fn default() -> Self {
+        SolutionCounts::new()
+    }
+}
+
+// A work stealing thread pool for running solvers. Every worker keeps its own
+// counts and they are only merged once the work is done, so nothing is locked
+// while searching. With a single thread there is no pool and everything runs on
+// the caller's thread, which is all wasm has.
+pub struct ParallelSearch {
+    pool: Option<rayon::ThreadPool>
+}
+
+impl ParallelSearch {
+    pub fn new(threads: usize) -> ParallelSearch {
+        if threads <= 1 {
+            return ParallelSearch{pool: None}
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("failed to build thread pool");
+        ParallelSearch{pool: Some(pool)}
+    }
+
+    pub fn with_available_parallelism() -> ParallelSearch {
+        ParallelSearch::new(available_parallelism())
+    }
+
+    // One pool for the whole process, built the first time it's asked for. Every
+    // agent, hint and server request samples on it rather than starting threads of its own
+    pub fn shared() -> Arc<ParallelSearch> {
+        static SHARED: OnceLock<Arc<ParallelSearch>> = OnceLock::new();
+        SHARED.get_or_init(|| Arc::new(ParallelSearch::with_available_parallelism())).clone()
+    }
+
+    pub fn threads(&self) -> usize {
+        self.pool.as_ref().map_or(1, rayon::ThreadPool::current_num_threads)
+    }
+
+    // Draws solutions from every solver until the deadline passes. A solver that
+    // finds no solution stops drawing.
+    pub fn sample<S, T, Sv>(&self, solvers: &mut [Sv], deadline: Instant) -> SolutionCounts<S, T> where
+        S: Copy + Debug + Hash + Eq + Send,
+        T: Copy + Debug + Hash + Eq + Send,
+        Sv: Solver<S, T> + Send
+    {
+        let draw = |solver: &mut Sv| {
+            let mut counts = SolutionCounts::new();
+            while Instant::now() < deadline {
+                match solver.solve() {
+                    None => break,
+                    Some(solution) => {
+                        let weight = solver.weight(&solution);
+                        counts.add_weighted_solution(&solution, weight)
+                    }
+                }
+            }
+            counts
+        };
+        match &self.pool {
+            None => solvers.iter_mut().map(draw).fold(SolutionCounts::new(), SolutionCounts::merge),
+            Some(pool) => pool.install(|| solvers.par_iter_mut().map(draw).reduce(SolutionCounts::new, SolutionCounts::merge))
+        }
+    }
+
+    // Enumerates every solution. The top of the search tree is split into
+    // subtrees that idle workers steal, below that each subtree is searched sequentially.
+    pub fn count_solutions<S, T, Strat>(&self, solver: &ConstraintSolver<S, T, Strat>) -> SolutionCounts<S, T> where
+        S: Copy + Debug + Hash + Eq + Send + Sync,
+        T: Copy + Debug + Hash + Eq + Send + Sync,
+        Strat: SelectionStrategy<S, T> + Clone + Send + Sync
+    {
+        let mut solver = solver.clone();
+        let points: Vec<S> = solver.variable_lookup.keys().copied().collect();
+        points.iter().for_each(|v_id| solver.set_variable_state(v_id, None));
+        let mut indices: HashSet<usize> = (0..points.len()).collect();
+        match &self.pool {
+            None => solver.count_from(&points, &mut indices, 0),
+            Some(pool) => {
+                // a few subtrees per worker keeps them all busy without cloning too often
+                let split_depth = (self.threads() * 4).next_power_of_two().trailing_zeros() as usize;
+                pool.install(|| solver.count_from(&points, &mut indices, split_depth))
+            }
+        }
+    }
+}
+
+pub fn available_parallelism() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+impl<S, T, Strat> ConstraintSolver<S, T, Strat> where
+    S: Copy + Debug + Hash + Eq + Send + Sync,
+    T: Copy + Debug + Hash + Eq + Send + Sync,
+    Strat: SelectionStrategy<S, T> + Clone + Send + Sync
+{
+    fn try_state(&mut self, v_id: &S, state: T) -> bool {
+        self.set_variable_state(v_id, Some(state));
+        self.constraints_are_satisfied(v_id) && self.forward_check(v_id)
+    }
+
+    fn count_from(&mut self, points: &[S], available_indices: &mut HashSet<usize>, split_depth: usize) -> SolutionCounts<S, T> {
+        let index = match self.selection_strategy.get_next_index(&self.variable_lookup, &self.variable_to_constraints, points, available_indices) {
+            None => {
+                let mut counts = SolutionCounts::new();
+                let solution = self.variable_lookup.iter()
+                    .filter_map(|(v_id, variable)| variable.value.map(|value| (*v_id, value)))
+                    .collect();
+                let weight = self.weight(&solution);
+                counts.add_weighted_solution(&solution, weight);
+                return counts
+            },
+            Some(index) => index
+        };
+        available_indices.remove(&index);
+        let v_id = points[index];
+        let states = self.variable_lookup.get(&v_id).unwrap().possible.to_vec();
+        let counts = if split_depth > 0 {
+            let parent: &Self = self;
+            let remaining: &HashSet<usize> = available_indices;
+            states.par_iter()
+                .map(|state| {
+                    let mut child = parent.clone();
+                    let mut child_indices = remaining.clone();
+                    if child.try_state(&v_id, *state) {
+                        child.count_from(points, &mut child_indices, split_depth - 1)
+                    } else {
+                        SolutionCounts::new()
+                    }
+                })
+                .reduce(SolutionCounts::new, SolutionCounts::merge)
+        } else {
+            let mut counts = SolutionCounts::new();
+            for state in states {
+                if self.try_state(&v_id, state) {
+                    counts = counts.merge(self.count_from(points, available_indices, 0));
+                }
+                self.set_variable_state(&v_id, None);
+            }
+            counts
+        };
+        available_indices.insert(index);
+        counts
+    }
+}
+
+// The value of a variable, or `hypothetical` if it is the variable being tried
+fn value_of<S, T>(variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S, hypothetical: Option<(&S, T)>) -> Option<T> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq
+{
+    match hypothetical {
+        Some((id, value)) if id == v_id => Some(value),
+        _ => variable_lookup.get(v_id).and_then(|variable| variable.value)
+    }
+}
+
+// The states of a variable that keep `feasible` true, its own value if it is already assigned
+fn consistent_states<S, T, F>(variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S, feasible: F) -> Vec<T> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq,
+    F: Fn(Option<(&S, T)>) -> bool
+{
+    let variable = match variable_lookup.get(v_id) {
+        None => return vec![],
+        Some(variable) => variable
+    };
+    let candidates = match variable.value {
+        Some(value) => vec![value],
+        None => variable.possible.to_vec()
+    };
+    candidates.into_iter()
+        .filter(|value| feasible(Some((v_id, *value))))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Equal,
+    AtMost,
+    AtLeast
+}
+
+// sum of coefficient * value over the variables, compared against a target
+pub struct LinearConstraint<S: Hash + Eq + Copy + Debug> {
+    terms: Vec<(S, i64)>,
+    relation: Relation,
+    target: i64
+}
+
+impl<S: Hash + Eq + Copy + Debug> LinearConstraint<S> {
+    pub fn new(terms: Vec<(S, i64)>, relation: Relation, target: i64) -> LinearConstraint<S> {
+        LinearConstraint{terms, relation, target}
+    }
+
+    pub fn sum(ids: &[S], relation: Relation, target: i64) -> LinearConstraint<S> {
+        LinearConstraint::new(ids.iter().map(|id| (*id, 1)).collect(), relation, target)
+    }
+
+    fn feasible<T>(&self, variable_lookup: &HashMap<S, Variable<S, T>>, hypothetical: Option<(&S, T)>) -> bool where
+        T: Copy + Debug + Hash + Eq + Into<i64>
+    {
+        let (low, high) = self.terms.iter()
+            .map(|(v_id, coefficient)| {
+                let values: Vec<i64> = match value_of(variable_lookup, v_id, hypothetical) {
+                    Some(value) => vec![value.into() * coefficient],
+                    None => match variable_lookup.get(v_id) {
+                        None => vec![0],
+                        Some(variable) => variable.possible.iter().map(|value| (*value).into() * coefficient).collect()
+                    }
+                };
+                (values.iter().copied().min().unwrap_or(0), values.iter().copied().max().unwrap_or(0))
+            })
+            .fold((0, 0), |acc, next| (acc.0 + next.0, acc.1 + next.1));
+        match self.relation {
+            Relation::Equal => low <= self.target && self.target <= high,
+            Relation::AtMost => low <= self.target,
+            Relation::AtLeast => high >= self.target
+        }
+    }
+}
+
+impl<S, T> Constraint<S, T> for LinearConstraint<S> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq + Into<i64>
+{
+    fn get_constrained_variable_ids(&self) -> Vec<S> {
+        self.terms.iter().map(|(v_id, _)| *v_id).collect()
+    }
+
+    fn check_constraint(&self, _: &HashMap<T, usize>, variable_lookup: &HashMap<S, Variable<S, T>>) -> bool {
+        self.feasible(variable_lookup, None)
+    }
+
+    fn consistent_states_for_variable(&self, variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S) -> Vec<T> {
+        consistent_states(variable_lookup, v_id, |hypothetical| self.feasible(variable_lookup, hypothetical))
+    }
+}
+
+// between min and max of the variables take the given value
+pub struct CardinalityConstraint<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> {
+    ids: Vec<S>,
+    value: T,
+    min: usize,
+    max: usize
+}
+
+impl<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> CardinalityConstraint<S, T> {
+    pub fn new(ids: Vec<S>, value: T, min: usize, max: usize) -> CardinalityConstraint<S, T> {
+        CardinalityConstraint{ids, value, min, max}
+    }
+
+    pub fn exactly(ids: Vec<S>, value: T, count: usize) -> CardinalityConstraint<S, T> {
+        CardinalityConstraint::new(ids, value, count, count)
+    }
+
+    fn feasible(&self, variable_lookup: &HashMap<S, Variable<S, T>>, hypothetical: Option<(&S, T)>) -> bool {
+        let (matching, possible) = self.ids.iter()
+            .map(|v_id| match value_of(variable_lookup, v_id, hypothetical) {
+                Some(value) => ((value == self.value) as usize, 0),
+                None => {
+                    let could_match = variable_lookup.get(v_id)
+                        .map(|variable| variable.possible.contains(&self.value))
+                        .unwrap_or(false);
+                    (0, could_match as usize)
+                }
+            })
+            .fold((0, 0), |acc, next| (acc.0 + next.0, acc.1 + next.1));
+        matching <= self.max && matching + possible >= self.min
+    }
+}
+
+impl<S, T> Constraint<S, T> for CardinalityConstraint<S, T> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq
+{
+    fn get_constrained_variable_ids(&self) -> Vec<S> {
+        self.ids.to_vec()
+    }
+
+    fn check_constraint(&self, _: &HashMap<T, usize>, variable_lookup: &HashMap<S, Variable<S, T>>) -> bool {
+        self.feasible(variable_lookup, None)
+    }
+
+    fn consistent_states_for_variable(&self, variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S) -> Vec<T> {
+        consistent_states(variable_lookup, v_id, |hypothetical| self.feasible(variable_lookup, hypothetical))
+    }
+}
+
+// no two of the variables share a value
+pub struct AllDifferentConstraint<S: Hash + Eq + Copy + Debug> {
+    ids: Vec<S>
+}
+
+impl<S: Hash + Eq + Copy + Debug> AllDifferentConstraint<S> {
+    pub fn new(ids: Vec<S>) -> AllDifferentConstraint<S> {
+        AllDifferentConstraint{ids}
+    }
+
+    fn feasible<T>(&self, variable_lookup: &HashMap<S, Variable<S, T>>, hypothetical: Option<(&S, T)>) -> bool where
+        T: Copy + Debug + Hash + Eq
+    {
+        let mut seen = HashSet::with_capacity(self.ids.len());
+        self.ids.iter()
+            .filter_map(|v_id| value_of(variable_lookup, v_id, hypothetical))
+            .all(|value| seen.insert(value))
+    }
+}
+
+impl<S, T> Constraint<S, T> for AllDifferentConstraint<S> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq
+{
+    fn get_constrained_variable_ids(&self) -> Vec<S> {
+        self.ids.to_vec()
+    }
+
+    fn check_constraint(&self, _: &HashMap<T, usize>, variable_lookup: &HashMap<S, Variable<S, T>>) -> bool {
+        self.feasible(variable_lookup, None)
+    }
+
+    fn consistent_states_for_variable(&self, variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S) -> Vec<T> {
+        consistent_states(variable_lookup, v_id, |hypothetical| self.feasible(variable_lookup, hypothetical))
+    }
+}
+
+// all of the variables share a value
+pub struct EqualityConstraint<S: Hash + Eq + Copy + Debug> {
+    ids: Vec<S>
+}
+
+impl<S: Hash + Eq + Copy + Debug> EqualityConstraint<S> {
+    pub fn new(ids: Vec<S>) -> EqualityConstraint<S> {
+        EqualityConstraint{ids}
+    }
+
+    fn feasible<T>(&self, variable_lookup: &HashMap<S, Variable<S, T>>, hypothetical: Option<(&S, T)>) -> bool where
+        T: Copy + Debug + Hash + Eq
+    {
+        let values: HashSet<T> = self.ids.iter()
+            .filter_map(|v_id| value_of(variable_lookup, v_id, hypothetical))
+            .collect();
+        match values.iter().next() {
+            None => true,
+            Some(value) => values.len() == 1 && self.ids.iter().all(|v_id| {
+                value_of(variable_lookup, v_id, hypothetical).is_some()
+                    || variable_lookup.get(v_id).map(|variable| variable.possible.contains(value)).unwrap_or(false)
+            })
+        }
+    }
+}
+
+impl<S, T> Constraint<S, T> for EqualityConstraint<S> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq
+{
+    fn get_constrained_variable_ids(&self) -> Vec<S> {
+        self.ids.to_vec()
+    }
+
+    fn check_constraint(&self, _: &HashMap<T, usize>, variable_lookup: &HashMap<S, Variable<S, T>>) -> bool {
+        self.feasible(variable_lookup, None)
+    }
+
+    fn consistent_states_for_variable(&self, variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S) -> Vec<T> {
+        consistent_states(variable_lookup, v_id, |hypothetical| self.feasible(variable_lookup, hypothetical))
+    }
+}
+
+// the variables, in order, must take one of the listed tuples
+pub struct TableConstraint<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> {
+    ids: Vec<S>,
+    tuples: Vec<Vec<T>>
+}
+
+impl<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq> TableConstraint<S, T> {
+    pub fn new(ids: Vec<S>, tuples: Vec<Vec<T>>) -> Option<TableConstraint<S, T>> {
+        if tuples.iter().any(|tuple| tuple.len() != ids.len()) {
+            return None
+        }
+        Some(TableConstraint{ids, tuples})
+    }
+
+    fn feasible(&self, variable_lookup: &HashMap<S, Variable<S, T>>, hypothetical: Option<(&S, T)>) -> bool {
+        self.tuples.iter().any(|tuple| {
+            self.ids.iter().zip(tuple.iter()).all(|(v_id, allowed)| {
+                match value_of(variable_lookup, v_id, hypothetical) {
+                    Some(value) => value == *allowed,
+                    None => variable_lookup.get(v_id).map(|variable| variable.possible.contains(allowed)).unwrap_or(false)
+                }
+            })
+        })
+    }
+}
+
+impl<S, T> Constraint<S, T> for TableConstraint<S, T> where
+    S: Copy + Debug + Hash + Eq,
+    T: Copy + Debug + Hash + Eq
+{
+    fn get_constrained_variable_ids(&self) -> Vec<S> {
+        self.ids.to_vec()
+    }
+
+    fn check_constraint(&self, _: &HashMap<T, usize>, variable_lookup: &HashMap<S, Variable<S, T>>) -> bool {
+        self.feasible(variable_lookup, None)
+    }
+
+    fn consistent_states_for_variable(&self, variable_lookup: &HashMap<S, Variable<S, T>>, v_id: &S) -> Vec<T> {
+        consistent_states(variable_lookup, v_id, |hypothetical| self.feasible(variable_lookup, hypothetical))
+    }
+}
+
+#[cfg(test)]
+mod constraint_tests {
+    use super::*;
+
+    type Shared<S, T> = Arc<dyn Constraint<S, T> + Send + Sync>;
+
+    fn variables<S: Hash + Eq + Copy + Debug, T: Copy + Debug + Hash + Eq>(ids: &[S], possible: &[T]) -> Vec<Variable<S, T>> {
+        ids.iter().map(|id| Variable{id: *id, value: None, possible: possible.to_vec()}).collect()
+    }
+
+    #[test]
+    fn solves_small_sudoku() {
+        // 4x4 sudoku, 0 is blank
+        let givens = [[1, 0, 0, 0],
+                      [0, 0, 3, 0],
+                      [0, 4, 0, 0],
+                      [0, 0, 0, 2]];
+        let ids: Vec<(usize, usize)> = (0..4).flat_map(|r| (0..4).map(move |c| (r, c))).collect();
+        let mut constraints: Vec<Shared<(usize, usize), i32>> = vec![];
+        for i in 0..4 {
+            constraints.push(Arc::new(AllDifferentConstraint::new((0..4).map(|c| (i, c)).collect())));
+            constraints.push(Arc::new(AllDifferentConstraint::new((0..4).map(|r| (r, i)).collect())));
+            let (r0, c0) = (2 * (i / 2), 2 * (i % 2));
+            constraints.push(Arc::new(AllDifferentConstraint::new(vec![(r0, c0), (r0, c0 + 1), (r0 + 1, c0), (r0 + 1, c0 + 1)])));
+        }
+        for (r, c) in &ids {
+            if givens[*r][*c] != 0 {
+                let tuples = vec![vec![givens[*r][*c]]];
+                constraints.push(Arc::new(TableConstraint::new(vec![(*r, *c)], tuples).unwrap()));
+            }
+        }
+        let mut solver = ConstraintSolver::new(variables(&ids, &[1, 2, 3, 4]), constraints, DegreeSelectionStrategy{});
+        let solution = solver.solve().expect("sudoku has a solution");
+        for i in 0..4 {
+            let row: HashSet<i32> = (0..4).map(|c| solution[&(i, c)]).collect();
+            let column: HashSet<i32> = (0..4).map(|r| solution[&(r, i)]).collect();
+            assert_eq!(row.len(), 4);
+            assert_eq!(column.len(), 4);
+        }
+        for (r, c) in &ids {
+            if givens[*r][*c] != 0 {
+                assert_eq!(solution[&(*r, *c)], givens[*r][*c]);
+            }
+        }
+    }
+
+    #[test]
+    fn solves_kakuro_run() {
+        // three distinct digits summing to 7 must be 1, 2 and 4
+        let ids = [0, 1, 2];
+        let constraints: Vec<Shared<usize, u8>> = vec![
+            Arc::new(LinearConstraint::sum(&ids, Relation::Equal, 7)),
+            Arc::new(AllDifferentConstraint::new(ids.to_vec()))
+        ];
+        let digits: Vec<u8> = (1..10).collect();
+        let mut solver = ConstraintSolver::new(variables(&ids, &digits), constraints, RandomSelectionStrategy{});
+        let solution = solver.solve().expect("kakuro run has a solution");
+        let mut values: Vec<u8> = ids.iter().map(|id| solution[id]).collect();
+        values.sort();
+        assert_eq!(values, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn cardinality_and_equality() {
+        let ids = [0, 1, 2, 3];
+        let constraints: Vec<Shared<usize, bool>> = vec![
+            Arc::new(CardinalityConstraint::exactly(ids.to_vec(), true, 2)),
+            Arc::new(EqualityConstraint::new(vec![0, 3]))
+        ];
+        let mut solver = ConstraintSolver::new(variables(&ids, &[false, true]), constraints, RandomSelectionStrategy{});
+        let solution = solver.solve().expect("should be satisfiable");
+        assert_eq!(ids.iter().filter(|id| solution[id]).count(), 2);
+        assert_eq!(solution[&0], solution[&3]);
+    }
+
+    #[test]
+    fn unsatisfiable_returns_none() {
+        let ids = [0, 1, 2];
+        let constraints: Vec<Shared<usize, bool>> = vec![
+            Arc::new(AllDifferentConstraint::new(ids.to_vec()))
+        ];
+        let mut solver = ConstraintSolver::new(variables(&ids, &[false, true]), constraints, RandomSelectionStrategy{});
+        assert!(solver.solve().is_none());
+    }
+
+    #[test]
+    fn parallel_count_matches_sequential() {
+        let ids: Vec<usize> = (0..10).collect();
+        let constraints: Vec<Shared<usize, bool>> = vec![
+            Arc::new(CardinalityConstraint::exactly(ids.to_vec(), true, 3)),
+            Arc::new(CardinalityConstraint::new(ids[..4].to_vec(), true, 0, 1))
+        ];
+        let solver = ConstraintSolver::new(variables(&ids, &[false, true]), constraints, RandomSelectionStrategy{});
+        // choose(6, 3) + 4 * choose(6, 2)
+        let expected = 20 + 4 * 15;
+        let single = ParallelSearch::new(1).count_solutions(&solver);
+        let many = ParallelSearch::new(4).count_solutions(&solver);
+        assert_eq!(single.solutions, expected);
+        assert_eq!(many.solutions, expected);
+        for id in &ids {
+            assert_eq!(single.count(id, &true), many.count(id, &true));
+            assert_eq!(many.count(id, &true) + many.count(id, &false), expected);
+        }
+        // each of the first four is mined in choose(6, 2) solutions
+        assert_eq!(many.count(&0, &true), 15);
+    }
+
+    #[test]
+    fn sampling_merges_every_worker() {
+        let ids: Vec<usize> = (0..6).collect();
+        let search = ParallelSearch::new(3);
+        let mut solvers: Vec<ConstraintSolver<usize, bool, RandomSelectionStrategy>> = (0..search.threads())
+            .map(|_| {
+                let constraints: Vec<Shared<usize, bool>> = vec![Arc::new(CardinalityConstraint::exactly(ids.to_vec(), true, 2))];
+                ConstraintSolver::new(variables(&ids, &[false, true]), constraints, RandomSelectionStrategy{})
+            })
+            .collect();
+        let deadline = Instant::now() + std::time::Duration::from_millis(20);
+        let counts = search.sample(&mut solvers, deadline);
+        assert!(counts.solutions > 0);
+        let mined: usize = ids.iter().map(|id| counts.count(id, &true)).sum();
+        assert_eq!(mined, 2 * counts.solutions);
+    }
+
+    // true is three times as believable as false for the one variable it covers
+    struct PreferTrue(u8);
+
+    impl Constraint<u8, bool> for PreferTrue {
+        fn get_constrained_variable_ids(&self) -> Vec<u8> {
+            vec![self.0]
+        }
+
+        fn check_constraint(&self, _: &HashMap<bool, usize>, _: &HashMap<u8, Variable<u8, bool>>) -> bool {
+            true
+        }
+
+        fn consistent_states_for_variable(&self, _: &HashMap<u8, Variable<u8, bool>>, _: &u8) -> Vec<bool> {
+            vec![false, true]
+        }
+
+        fn weight(&self, solution: &HashMap<u8, bool>) -> f64 {
+            if solution[&self.0] { 3.0 } else { 1.0 }
+        }
+    }
+
+    #[test]
+    fn soft_constraints_weight_counts() {
+        let ids = [0u8, 1];
+        let constraints: Vec<Shared<u8, bool>> = vec![
+            Arc::new(CardinalityConstraint::exactly(ids.to_vec(), true, 1)),
+            Arc::new(PreferTrue(0))
+        ];
+        let solver = ConstraintSolver::new(variables(&ids, &[false, true]), constraints, RandomSelectionStrategy{});
+        let counts = ParallelSearch::new(2).count_solutions(&solver);
+        assert_eq!(counts.solutions, 2);
+        assert_eq!(counts.count(&0, &true), 1);
+        assert!((counts.frequency(&0, &true) - 0.75).abs() < 1e-6);
+        assert!((counts.frequency(&1, &true) - 0.25).abs() < 1e-6);
+    }
+}
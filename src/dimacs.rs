@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use super::board::Board;
+use super::board::Point;
+use super::ai::build_mine_constraints;
+use super::sat::SatSolver;
+
+// DIMACS literals: variables are numbered from 1, negative means negated
+pub type Literal = i32;
+
+#[derive(Debug, Default, Clone)]
+pub struct Cnf {
+    pub variable_count: usize,
+    pub clauses: Vec<Vec<Literal>>
+}
+
+impl Cnf {
+    pub fn new() -> Cnf {
+        Cnf{variable_count: 0, clauses: vec![]}
+    }
+
+    pub fn new_variable(&mut self) -> Literal {
+        self.variable_count += 1;
+        self.variable_count as Literal
+    }
+
+    pub fn add_clause(&mut self, clause: Vec<Literal>) {
+        self.clauses.push(clause);
+    }
+
+    pub fn add_at_most(&mut self, literals: &[Literal], k: i32) {
+        // sequential counter encoding (Sinz 2005), register s[i][j] is true
+        // when at least j+1 of the first i+1 literals are true
+        if k < 0 {
+            self.add_clause(vec![]);
+            return
+        }
+        let k = k as usize;
+        let n = literals.len();
+        if k >= n {
+            return
+        }
+        if k == 0 {
+            literals.iter().for_each(|lit| self.add_clause(vec![-lit]));
+            return
+        }
+        let registers: Vec<Vec<Literal>> = (0..n-1)
+            .map(|_| (0..k).map(|_| self.new_variable()).collect())
+            .collect();
+
+        self.add_clause(vec![-literals[0], registers[0][0]]);
+        for register in &registers[0][1..] {
+            self.add_clause(vec![-register]);
+        }
+        for i in 1..n-1 {
+            self.add_clause(vec![-literals[i], registers[i][0]]);
+            self.add_clause(vec![-registers[i-1][0], registers[i][0]]);
+            for j in 1..k {
+                self.add_clause(vec![-literals[i], -registers[i-1][j-1], registers[i][j]]);
+                self.add_clause(vec![-registers[i-1][j], registers[i][j]]);
+            }
+            self.add_clause(vec![-literals[i], -registers[i-1][k-1]]);
+        }
+        self.add_clause(vec![-literals[n-1], -registers[n-2][k-1]]);
+    }
+
+    pub fn add_at_least(&mut self, literals: &[Literal], k: i32) {
+        let negated: Vec<Literal> = literals.iter().map(|lit| -lit).collect();
+        self.add_at_most(&negated, literals.len() as i32 - k);
+    }
+
+    pub fn add_exactly(&mut self, literals: &[Literal], k: i32) {
+        self.add_at_most(literals, k);
+        self.add_at_least(literals, k);
+    }
+
+    pub fn is_satisfied_by(&self, model: &[Literal]) -> bool {
+        let assignment: HashMap<Literal, bool> = model.iter()
+            .map(|lit| (lit.abs(), *lit > 0))
+            .collect();
+        self.clauses.iter().all(|clause| {
+            clause.iter().any(|lit| assignment.get(&lit.abs()) == Some(&(*lit > 0)))
+        })
+    }
+
+    pub fn to_dimacs(&self) -> String {
+        let mut result = format!("p cnf {} {}\n", self.variable_count, self.clauses.len());
+        for clause in &self.clauses {
+            for lit in clause {
+                result += &lit.to_string();
+                result += " ";
+            }
+            result += "0\n";
+        }
+        result
+    }
+}
+
+impl fmt::Display for Cnf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_dimacs())
+    }
+}
+
+// Reads solver output in the SAT competition format ("s ..." and "v ..." lines).
+// A bare list of literals is also accepted. Returns None if the solver
+// reported the formula as unsatisfiable or gave no model.
+pub fn parse_model(output: &str) -> Option<Vec<Literal>> {
+    let mut model = vec![];
+    for line in output.lines() {
+        let line = line.trim();
+        if line.starts_with('c') || line.is_empty() {
+            continue
+        }
+        if line.starts_with('s') {
+            if line.contains("UNSAT") {
+                return None
+            }
+            continue
+        }
+        let values = line.trim_start_matches('v').split_whitespace();
+        for value in values {
+            let lit: Literal = value.parse().ok()?;
+            if lit != 0 {
+                model.push(lit);
+            }
+        }
+    }
+    if model.is_empty() {
+        None
+    } else {
+        Some(model)
+    }
+}
+
+// The frontier of a board as CNF. Each border point gets one variable per mine
+// it could hold, the j-th being true when the point holds at least j mines,
+// followed by the auxiliary variables of the cardinality encodings
+pub struct MineEncoding {
+    pub cnf: Cnf,
+    points: Vec<Point>,
+    variables: HashMap<Point, Vec<Literal>>
+}
+
+impl MineEncoding {
+    pub fn from_board(board: &Board) -> MineEncoding {
+        let mut cnf = Cnf::new();
+        let points = board.get_border_points();
+        let variables: HashMap<Point, Vec<Literal>> = points.iter()
+            .map(|point| (*point, (0..board.mines_per_cell()).map(|_| cnf.new_variable()).collect()))
+            .collect();
+        for literals in variables.values() {
+            literals.windows(2).for_each(|pair| cnf.add_clause(vec![pair[0], -pair[1]]));
+        }
+
+        for constraint in build_mine_constraints(board) {
+            let constrained = if constraint.global { &points } else { &constraint.constrained_points };
+            let literals: Vec<Literal> = constrained.iter()
+                .flat_map(|point| variables[point].iter().copied())
+                .collect();
+            let negated: Vec<Literal> = literals.iter().map(|lit| -lit).collect();
+            cnf.add_at_most(&literals, constraint.max_mines);
+            cnf.add_at_most(&negated, literals.len() as i32 - constraint.min_mines);
+        }
+        MineEncoding{cnf, points, variables}
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    // the variable that is true when the point holds any mine
+    pub fn variable_for_point(&self, point: &Point) -> Option<Literal> {
+        self.variables.get(point).map(|literals| literals[0])
+    }
+
+    // Whether every layout that fits the numbers puts exactly this many mines on the
+    // point, by asking the solver for one with fewer and one with more. Sampling can
+    // only say it never came across either
+    pub fn forces(&self, point: &Point, mines: usize) -> bool {
+        let literals = match self.variables.get(point) {
+            Some(literals) if mines <= literals.len() => literals,
+            _ => return false
+        };
+        let fewer = mines.checked_sub(1).map(|index| -literals[index]);
+        let more = literals.get(mines).copied();
+        fewer.into_iter().chain(more).all(|opposite| !self.satisfiable_with(opposite))
+    }
+
+    fn satisfiable_with(&self, literal: Literal) -> bool {
+        let mut solver = SatSolver::new(self.cnf.variable_count);
+        self.cnf.clauses.iter().for_each(|clause| solver.add_clause(clause));
+        solver.add_clause(&[literal]);
+        solver.solve().is_some()
+    }
+
+    // how many mines the model puts in each point
+    pub fn assignments_from_model(&self, model: &[Literal]) -> HashMap<Point, usize> {
+        let values: HashMap<Literal, bool> = model.iter()
+            .map(|lit| (lit.abs(), *lit > 0))
+            .collect();
+        self.points.iter()
+            .filter_map(|point| {
+                let mines: Option<Vec<bool>> = self.variables[point].iter()
+                    .map(|lit| values.get(lit).copied())
+                    .collect();
+                mines.map(|mines| (*point, mines.into_iter().filter(|mined| *mined).count()))
+            })
+            .collect()
+    }
+
+    // the point variables, the ones a layout of mines decides
+    pub fn projection(&self) -> Vec<Literal> {
+        self.points.iter().flat_map(|point| self.variables[point].iter().copied()).collect()
+    }
+
+    // The registers of the cardinality encodings are only forced one way, so a layout
+    // can have several models. The "c ind" (ApproxMC, older #SAT tools) and "c p show"
+    // (model counting competition) lines project counting onto the point variables
+    pub fn to_dimacs(&self) -> String {
+        let mut result = String::new();
+        for point in &self.points {
+            let literals: Vec<String> = self.variables[point].iter().map(|lit| lit.to_string()).collect();
+            result += &format!("c {} {}\n", literals.join(" "), point);
+        }
+        let projection: String = self.projection().iter().map(|lit| lit.to_string() + " ").collect();
+        result += &format!("c ind {}0\nc p show {}0\n", projection, projection);
+        result + &self.cnf.to_dimacs()
+    }
+
+    // Runs an external solver binary as `solver <file>` and reads the model back.
+    // Solvers conventionally exit with 10 (SAT) or 20 (UNSAT) so the exit status is ignored
+    pub fn solve_with(&self, solver: &str, cnf_path: &Path) -> io::Result<Option<HashMap<Point, usize>>> {
+        fs::write(cnf_path, self.to_dimacs())?;
+        let output = Command::new(solver).arg(cnf_path).output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(parse_model(&stdout).map(|model| self.assignments_from_model(&model)))
+    }
+}
+
+#[cfg(test)]
+mod dimacs_tests {
+    use super::*;
+    use crate::board::BoardSize;
+
+    fn model_from_bits(bits: usize, n: usize) -> Vec<Literal> {
+        (0..n).map(|i| if bits & (1 << i) != 0 { i as Literal + 1 } else { -(i as Literal + 1) }).collect()
+    }
+
+    // brute force over the auxiliary variables to check the encoding is satisfiable
+    fn extends_to_model(cnf: &Cnf, assignment: &[Literal]) -> bool {
+        let n = assignment.len();
+        let aux = cnf.variable_count - n;
+        (0..1usize << aux).any(|bits| {
+            let mut model = assignment.to_vec();
+            model.extend(model_from_bits(bits, aux).iter().map(|lit| lit.signum() * (lit.abs() + n as Literal)));
+            cnf.is_satisfied_by(&model)
+        })
+    }
+
+    #[test]
+    fn at_most_matches_count() {
+        for n in 1..5usize {
+            for k in -1..7i32 {
+                let mut cnf = Cnf::new();
+                let literals: Vec<Literal> = (0..n).map(|_| cnf.new_variable()).collect();
+                cnf.add_at_most(&literals, k);
+                for bits in 0..1usize << n {
+                    let ones = bits.count_ones() as i32;
+                    assert_eq!(extends_to_model(&cnf, &model_from_bits(bits, n)), ones <= k);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exactly_matches_count() {
+        for n in 1..5usize {
+            for k in 0..6i32 {
+                let mut cnf = Cnf::new();
+                let literals: Vec<Literal> = (0..n).map(|_| cnf.new_variable()).collect();
+                cnf.add_exactly(&literals, k);
+                for bits in 0..1usize << n {
+                    let ones = bits.count_ones() as i32;
+                    assert_eq!(extends_to_model(&cnf, &model_from_bits(bits, n)), ones == k);
+                }
+            }
+        }
+    }
+
+    // models counted over the projection, blocking each layout once it's found
+    fn projected_count(cnf: &Cnf, projection: &[Literal]) -> usize {
+        let mut blocked: Vec<Vec<Literal>> = vec![];
+        loop {
+            let mut solver = SatSolver::new(cnf.variable_count);
+            cnf.clauses.iter().chain(&blocked).for_each(|clause| solver.add_clause(clause));
+            match solver.solve() {
+                None => return blocked.len(),
+                Some(model) => blocked.push(projection.iter()
+                    .map(|lit| if model[(lit - 1) as usize] { -lit } else { *lit })
+                    .collect())
+            }
+        }
+    }
+
+    // every way of putting up to mines_per_cell mines on each border point the constraints allow
+    fn brute_force_count(board: &Board) -> usize {
+        let points = board.get_border_points();
+        let constraints = build_mine_constraints(board);
+        let per_cell = board.mines_per_cell() + 1;
+        (0..per_cell.pow(points.len() as u32)).filter(|layout| {
+            let mines: HashMap<Point, i32> = points.iter().enumerate()
+                .map(|(i, point)| (*point, (layout / per_cell.pow(i as u32) % per_cell) as i32))
+                .collect();
+            constraints.iter().all(|constraint| {
+                let constrained = if constraint.global { &points } else { &constraint.constrained_points };
+                let total: i32 = constrained.iter().map(|point| mines[point]).sum();
+                constraint.min_mines <= total && total <= constraint.max_mines
+            })
+        }).count()
+    }
+
+    #[test]
+    fn projected_counts_match_layouts() {
+        // with slack in the bound the registers are free, only the projection counts layouts
+        let mut cnf = Cnf::new();
+        let literals: Vec<Literal> = (0..3).map(|_| cnf.new_variable()).collect();
+        cnf.add_at_most(&literals, 2);
+        let everything: Vec<Literal> = (1..=cnf.variable_count as Literal).collect();
+        assert_eq!(projected_count(&cnf, &literals), 7);
+        assert!(projected_count(&cnf, &everything) > 7);
+
+        // the bottom row is a guess: one 1 (or 2) for the two cells under it
+        let mut single = Board::new_with_mines(BoardSize::new(2, 4), &[Point(3, 0, 0)]).unwrap();
+        single.probe(&Point(0, 0, 0));
+        let mut multi = Board::new_with_mines(BoardSize::new(2, 4), &[Point(3, 0, 0), Point(3, 0, 0)]).unwrap();
+        multi.probe(&Point(0, 0, 0));
+        for board in [single, multi].iter() {
+            let encoding = MineEncoding::from_board(board);
+            let expected = brute_force_count(board);
+            assert!(expected > 1);
+            assert_eq!(projected_count(&encoding.cnf, &encoding.projection()), expected);
+            let shown = encoding.projection().iter().map(|lit| lit.to_string() + " ").collect::<String>();
+            assert!(encoding.to_dimacs().contains(&format!("c p show {}0\n", shown)));
+        }
+    }
+
+    #[test]
+    fn forces_only_what_every_layout_agrees_on() {
+        // 1 2 1 over three unknowns: the middle is safe and both ends are mines
+        let mut board = Board::new_with_mines(BoardSize::new(3, 2), &[Point(1, 0, 0), Point(1, 2, 0)]).unwrap();
+        (0..3).for_each(|column| { board.probe(&Point(0, column, 0)); });
+        let encoding = MineEncoding::from_board(&board);
+        assert!(encoding.forces(&Point(1, 1, 0), 0));
+        assert!(encoding.forces(&Point(1, 0, 0), 1));
+        assert!(!encoding.forces(&Point(1, 0, 0), 0));
+
+        // one mine somewhere under a lone 1 is never settled either way
+        let mut board = Board::new_with_mines(BoardSize::new(2, 4), &[Point(3, 0, 0)]).unwrap();
+        board.probe(&Point(0, 0, 0));
+        let encoding = MineEncoding::from_board(&board);
+        for point in encoding.points() {
+            assert!(!encoding.forces(point, 0) && !encoding.forces(point, 1));
+        }
+        assert!(!encoding.forces(&Point(0, 0, 0), 0));
+
+        // two mines in one cell are only forced as two
+        let mut board = Board::new_with_mines(BoardSize::new(3, 2), &[Point(1, 0, 0), Point(1, 0, 0), Point(1, 2, 0), Point(1, 2, 0)]).unwrap();
+        (0..3).for_each(|column| { board.probe(&Point(0, column, 0)); });
+        let encoding = MineEncoding::from_board(&board);
+        assert!(encoding.forces(&Point(1, 0, 0), 2));
+        assert!(!encoding.forces(&Point(1, 0, 0), 1));
+    }
+
+    #[test]
+    fn parse_competition_output() {
+        let output = "c comment\ns SATISFIABLE\nv 1 -2 3\nv -4 0\n";
+        assert_eq!(parse_model(output), Some(vec![1, -2, 3, -4]));
+        assert_eq!(parse_model("s UNSATISFIABLE\n"), None);
+    }
+}
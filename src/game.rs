@@ -0,0 +1,73 @@
+use super::board::Board;
+use super::board::Content;
+use super::json::Json;
+use super::protocol;
+use super::ActionType;
+
+// A game played one request at a time rather than through game_loop, for the
+// server and the browser: the board and whether a mine has gone off
+pub struct Game {
+    board: Board,
+    lost: bool
+}
+
+impl Game {
+    pub fn new(board: Board) -> Game {
+        Game{board, lost: false}
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.lost || self.board.is_won()
+    }
+
+    // "playing", "won" or "lost"
+    pub fn status(&self) -> &'static str {
+        if self.lost {
+            "lost"
+        } else if self.board.is_won() {
+            "won"
+        } else {
+            "playing"
+        }
+    }
+
+    // makes the move or says why it can't be made
+    pub fn play(&mut self, action: ActionType) -> Result<(), String> {
+        if self.is_over() {
+            return Err(String::from("the game is over"))
+        }
+        if !self.board.size.is_playable(&action.point()) {
+            return Err(format!("can't {}, that cell is not on the board", action))
+        }
+        self.lost = super::make_move(&mut self.board, action) > 0;
+        Ok(())
+    }
+
+    // The player's view from the bot protocol with the status and, once dealt, the
+    // "first_click" policy. Once the game is over "mine_cells" lists where the mines
+    // were as [row, column, layer]
+    pub(crate) fn view(&self) -> Json {
+        let mut fields = vec![(String::from("status"), Json::string(self.status()))];
+        if let Json::Object(view) = protocol::player_view(&self.board) {
+            fields.extend(view.into_iter().filter(|(key, _)| key != "type"));
+        }
+        // the policy the first click really got, front ends can say if it's weaker than asked for
+        if let Some(dealt) = self.board.dealt_with() {
+            fields.push((String::from("first_click"), Json::string(&dealt.name())));
+        }
+        if self.is_over() {
+            let mined = self.board.size.points().into_iter()
+                .filter(|point| matches!(self.board.retrieve_cell(point).content, Content::Mine(_)))
+                .map(|point| Json::Array(vec![
+                    Json::Number(point.0 as f64), Json::Number(point.1 as f64), Json::Number(point.2 as f64)
+                ]))
+                .collect();
+            fields.push((String::from("mine_cells"), Json::Array(mined)));
+        }
+        Json::Object(fields)
+    }
+}
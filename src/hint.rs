@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+use std::fmt;
+use super::ai::NaiveAI;
+use super::board::Board;
+use super::board::ClueNoise;
+use super::board::Point;
+use super::dimacs::MineEncoding;
+use super::ActionType;
+
+// Why a move is worth making, for hints and for the AI's own moves
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reason {
+    // nothing is revealed yet
+    Opening,
+    // the number at source already touches as many mines as it shows,
+    // the rest of its neighbours are safe. Flags are taken to be mines,
+    // the explanation says so when there are any
+    Satisfied{source: Point, clue: usize, flags: usize},
+    // the number at source needs every unknown neighbour it has left,
+    // with the same trust in its flags
+    Saturated{source: Point, clue: usize, flags: usize, unknown: usize},
+    // none of the mine layouts that fit the numbers put a mine here
+    NeverMined,
+    // every mine layout that fits the numbers puts a mine here
+    AlwaysMined,
+    // nothing is certain, this is the cell least likely to hold a mine
+    // (or most likely, for a flag)
+    Guess{probability: f32},
+    // nothing to go on at all
+    Random
+}
+
+// a move and why, the AI's moves come as hints it takes itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hint {
+    pub action: ActionType,
+    pub reason: Reason
+}
+
+impl Hint {
+    pub fn new(action: ActionType, reason: Reason) -> Hint {
+        Hint{action, reason}
+    }
+
+    pub fn point(&self) -> Point {
+        self.action.point()
+    }
+
+    // true unless it's a guess, a forced move is only as good as the flags it counts
+    pub fn is_forced(&self) -> bool {
+        !matches!(self.reason, Reason::Guess{..} | Reason::Opening | Reason::Random)
+    }
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.action)?;
+        let target = cell_name(&self.point());
+        match &self.reason {
+            Reason::Opening => write!(f, "nothing is open yet, {} is as good a start as any", target),
+            Reason::Satisfied{source, clue: 0, ..} => write!(f, "cell {} shows 0, so {} is safe", cell_name(source), target),
+            Reason::Satisfied{source, clue, flags} =>
+                write!(f, "cell {} shows {} and already touches {}, so {} is safe{}",
+                       cell_name(source), clue, plural(*flags, "flag"), target, trusting(*flags)),
+            Reason::Saturated{source, clue, flags, unknown} =>
+                write!(f, "cell {} shows {}, touches {} and has {} left, so {} is a mine{}",
+                       cell_name(source), clue, plural(*flags, "flag"), plural(*unknown, "unknown neighbour"), target,
+                       trusting(*flags)),
+            Reason::NeverMined => write!(f, "no layout of mines that fits the numbers puts one on {}", target),
+            Reason::AlwaysMined => write!(f, "every layout of mines that fits the numbers puts one on {}", target),
+            Reason::Guess{probability} => match self.action {
+                ActionType::Flag(_) =>
+                    write!(f, "nothing is certain, {} is the likeliest mine with a {:.0}% chance", target, probability * 100.0),
+                _ => write!(f, "nothing is certain, {} is the safest guess with a {:.0}% chance of a mine", target, probability * 100.0)
+            },
+            Reason::Random => write!(f, "no layout of mines fits the numbers, {} is a shot in the dark", target)
+        }
+    }
+}
+
+fn cell_name(point: &Point) -> String {
+    if point.2 == 0 {
+        format!("({},{})", point.0, point.1)
+    } else {
+        format!("({},{},{})", point.0, point.1, point.2)
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+// the rules count flags as mines without checking them, a wrong one makes the hint wrong
+fn trusting(flags: usize) -> &'static str {
+    match flags {
+        0 => "",
+        1 => " as long as the flag is right",
+        _ => " as long as the flags are right"
+    }
+}
+
+// The next move for a person stuck on this board. Forced moves come first, safe
+// clicks before flags, then whatever sampling turns up and the solver proves, and
+// if nothing is sure the cell with the lowest chance of a mine. None once nothing
+// is unknown
+pub fn hint(board: &Board) -> Option<Hint> {
+    hint_within(board, 1000)
+}
+
+// hint, giving the sampling at most max_time milliseconds
+pub fn hint_within(board: &Board, max_time: u64) -> Option<Hint> {
+    let unknown = board.get_unknown_points();
+    if unknown.is_empty() {
+        return None
+    }
+    if !board.initialized {
+        let start = *board.size.points().first()?;
+        return Some(Hint::new(ActionType::Click(start), Reason::Opening))
+    }
+    // the rules take every number at its word
+    if board.clue_noise() == ClueNoise::Exact {
+        if let Some(hint) = rule_hints(board).into_iter().next() {
+            return Some(hint)
+        }
+    }
+
+    let probabilities = NaiveAI::new(0, max_time).with_verbose(false).mine_probabilities(board);
+    from_probabilities(board, &unknown, probabilities)
+}
+
+// What the sampled chances of a mine next to the numbers say to do, the cells
+// left out of them share the mines the border doesn't take. A sample that never
+// (or always) had a mine somewhere is only a lead, the solver has to prove it
+fn from_probabilities(board: &Board, unknown: &[Point], probabilities: Vec<(Point, f32)>) -> Option<Hint> {
+    let mut encoding = None;
+    let mut proven = |point: &Point, mines: usize| encoding.get_or_insert_with(|| MineEncoding::from_board(board)).forces(point, mines);
+    if let Some((point, _)) = probabilities.iter().find(|(point, probability)| *probability == 0.0 && proven(point, 0)) {
+        return Some(Hint::new(ActionType::Click(*point), Reason::NeverMined))
+    }
+    // more than one mine per cell needs the exact count, which the probabilities don't give
+    if board.mines_per_cell() == 1 {
+        if let Some((point, _)) = probabilities.iter().find(|(point, probability)| *probability >= 1.0 && proven(point, 1)) {
+            return Some(Hint::new(ActionType::Flag(*point), Reason::AlwaysMined))
+        }
+    }
+
+    let interior: Vec<&Point> = unknown.iter()
+        .filter(|point| !probabilities.iter().any(|(border, _)| border == *point))
+        .collect();
+    let border_mines: f32 = probabilities.iter().map(|(_, probability)| probability).sum();
+    let interior_guess = interior.first().map(|point| {
+        let left = (board.remaining_mines() as f32 - border_mines).max(0.0);
+        (**point, (left / interior.len() as f32).min(1.0))
+    });
+    let (point, probability) = probabilities.into_iter()
+        .chain(interior_guess)
+        .fold(None, |best: Option<(Point, f32)>, (point, probability)| match best {
+            Some((_, lowest)) if lowest <= probability => best,
+            _ => Some((point, probability))
+        })?;
+    Some(Hint::new(ActionType::Click(point), Reason::Guess{probability}))
+}
+
+// Every move the numbers force on their own, one per cell with the first number that
+// forces it. Safe clicks come before flags
+pub(crate) fn rule_hints(board: &Board) -> Vec<Hint> {
+    let sources: Vec<Point> = board.size.points().into_iter()
+        .filter(|point| board.retrieve_cell(point).is_known_unmined() && board.has_unknown_neighbors(point))
+        .collect();
+    let explain = |source: &Point| (board.retrieve_cell(source).clue, board.count_assumed_mined_neighbors(source));
+    let mut seen: HashSet<Point> = HashSet::new();
+    let mut hints = vec![];
+    for source in &sources {
+        let (clue, flags) = explain(source);
+        for safe in board.known_safe_neighbors(source) {
+            if seen.insert(safe) {
+                hints.push(Hint::new(ActionType::Click(safe), Reason::Satisfied{source: *source, clue, flags}));
+            }
+        }
+    }
+    for source in &sources {
+        let mined = board.known_flaggable_neighbors(source);
+        let (clue, flags) = explain(source);
+        for mine in &mined {
+            if seen.insert(*mine) {
+                let reason = Reason::Saturated{source: *source, clue, flags, unknown: mined.len()};
+                hints.push(Hint::new(ActionType::Flag(*mine), reason));
+            }
+        }
+    }
+    hints
+}
+
+#[cfg(test)]
+mod hint_tests {
+    use super::*;
+    use crate::board::BoardSize;
+
+    #[test]
+    fn rules_come_with_their_cell() {
+        // one row: the 1 at (0,0) can only mean (0,1)
+        let mut board = Board::new_with_mines(BoardSize::new(4, 1), &[Point(0, 1, 0)]).unwrap();
+        board.probe(&Point(0, 0, 0));
+        let hint = super::hint(&board).unwrap();
+        assert_eq!(hint.reason, Reason::Saturated{source: Point(0, 0, 0), clue: 1, flags: 0, unknown: 1});
+        assert_eq!(hint.to_string(), "flag 0 1: cell (0,0) shows 1, touches 0 flags and has 1 unknown neighbour left, so (0,1) is a mine");
+
+        // with the flag down the 1 at (0,2) is used up
+        board.toggle_flag(&Point(0, 1, 0));
+        board.probe(&Point(0, 2, 0));
+        let hint = super::hint(&board).unwrap();
+        assert!(hint.is_forced());
+        assert_eq!(hint.reason, Reason::Satisfied{source: Point(0, 2, 0), clue: 1, flags: 1});
+        assert_eq!(hint.to_string(), "click 0 3: cell (0,2) shows 1 and already touches 1 flag, so (0,3) is safe as long as the flag is right");
+    }
+
+    #[test]
+    fn guesses_say_how_risky() {
+        // the corner 1 spreads one mine over three cells, the other is somewhere in the open
+        let mut board = Board::new_with_mines(BoardSize::new(6, 6), &[Point(1, 1, 0), Point(5, 5, 0)]).unwrap();
+        board.probe(&Point(0, 0, 0));
+        // however little the sampling finds in the time, every cell is at most 2 in 35
+        let hint = hint_within(&board, 50).unwrap();
+        match hint.reason {
+            Reason::Guess{probability} => assert!(probability < 0.1),
+            ref reason => panic!("expected a guess, got {:?}", reason)
+        }
+        assert!(!hint.is_forced());
+        assert!(hint.to_string().contains("% chance of a mine"));
+
+        // with the exact chances the border is a third each and the open cells share the other mine
+        let border = board.get_unknown_points().into_iter()
+            .filter(|point| board.neighbor_points(&Point(0, 0, 0)).contains(point))
+            .map(|point| (point, 1.0 / 3.0))
+            .collect();
+        let hint = from_probabilities(&board, &board.get_unknown_points(), border).unwrap();
+        assert!(!board.neighbor_points(&Point(0, 0, 0)).contains(&hint.point()));
+        assert_eq!(hint.reason, Reason::Guess{probability: 1.0 / 32.0});
+        assert_eq!(hint.to_string(), "click 0 2: nothing is certain, (0,2) is the safest guess with a 3% chance of a mine");
+
+        // a sample that happened to miss a mine doesn't make the cell safe
+        let unlucky = vec![(Point(0, 1, 0), 0.0), (Point(1, 0, 0), 0.5), (Point(1, 1, 0), 1.0)];
+        let hint = from_probabilities(&board, &board.get_unknown_points(), unlucky).unwrap();
+        assert_eq!(hint.reason, Reason::Guess{probability: 0.0});
+        assert!(!hint.is_forced());
+    }
+
+    #[test]
+    fn certain_hints_are_proven() {
+        // the top row shows 1 2 1, only together do they settle the bottom row
+        let mut board = Board::new_with_mines(BoardSize::new(3, 2), &[Point(1, 0, 0), Point(1, 2, 0)]).unwrap();
+        (0..3).for_each(|column| { board.probe(&Point(0, column, 0)); });
+        let hint = hint_within(&board, 50).unwrap();
+        assert_eq!(hint, Hint::new(ActionType::Click(Point(1, 1, 0)), Reason::NeverMined));
+        assert!(hint.is_forced());
+    }
+
+    #[test]
+    fn openings_and_finished_boards() {
+        let mut board = Board::new_from_ints(5, 5, 0).unwrap();
+        assert_eq!(hint(&board).map(|hint| hint.reason), Some(Reason::Opening));
+        board.probe(&Point(2, 2, 0));
+        assert_eq!(hint(&board), None);
+    }
+}
@@ -1,58 +1,223 @@
-use regex::Regex;
+use std::collections::VecDeque;
 use std::io;
+use std::iter::Peekable;
 use super::board::Board;
 use super::board::Point;
 use super::Agent;
 use super::ActionType;
+use super::Command;
 
+const HELP: &str = "Moves take a cell as ROW COLUMN [LAYER] or chess style, letters for the column: B7 [LAYER]
+  click, c, open       reveal a cell
+  flag, f              flag a cell (again for more flags on multi-mine boards)
+  chord, ch            reveal around a number that has all its flags
+  complete, a          flag around a number that can't be anything else
+  undo, u              take the last move back
+  hint                 suggest a move and say why
+  probabilities, p     show how likely each cell is to be a mine
+  save FILE, load FILE keep the game for later, or pick one up again
+  new, n               start over on a new board
+  quit, q              give up
+  help, h, ?           this
+Several commands can go on one line: c 3 4 f B2; hint";
+
+// a command for the loop, or something the agent answers itself
+#[derive(Debug, PartialEq)]
+enum Request {
+    Help,
+    Command(Command)
+}
+
+#[derive(Default)]
 pub struct HumanAgent {
+    // commands from a line that had more than one
+    pending: VecDeque<Command>
+}
+
+impl HumanAgent {
+    pub fn new() -> HumanAgent {
+        HumanAgent::default()
+    }
 }
 
 impl Agent for HumanAgent {
     fn generate_move(&mut self, board: &Board) -> ActionType {
-        println!("Please input your move: TYPE X Y");
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).expect("Failed to read");
-        match HumanAgent::action_from_string(&input) {
-            Some(action) => action,
-            None => {
-                println!("Must be of the form: TYPE X Y");
-                self.generate_move(board)
+        loop {
+            match self.next_command(board) {
+                Command::Move(action) => return action,
+                command => println!("can't {:?} here, only moves", command)
             }
         }
     }
-}
 
-impl HumanAgent {
-    fn action_from_string(input: &str) -> Option<ActionType>{
-        let re = Regex::new(r"(click|flag|chord|complete)\s(\d+)\s(\d+)").unwrap();
-        match re.captures_iter(input).next() {
-            None => None,
-            Some(cap) => {
-                let x: usize = cap[2].parse().expect("Expected a number");
-                let y: usize = cap[3].parse().expect("Expected a number");
-                let point = Point(x, y);
-                HumanAgent::extract_type_from_string(&cap[1], point)
+    fn next_command(&mut self, _board: &Board) -> Command {
+        loop {
+            if let Some(command) = self.pending.pop_front() {
+                return command
+            }
+            println!("Please input your move (help lists the commands)");
+            let mut input = String::new();
+            if io::stdin().read_line(&mut input).expect("Failed to read") == 0 {
+                return Command::Quit
+            }
+            match parse_line(&input) {
+                Err(problem) => println!("{}", problem),
+                Ok(requests) => for request in requests {
+                    match request {
+                        Request::Help => println!("{}", HELP),
+                        Request::Command(command) => self.pending.push_back(command)
+                    }
+                }
             }
         }
     }
+}
 
-    fn extract_type_from_string(input: &str, point: Point) -> Option<ActionType>{
-        // there must be a better way
-        if input == "click"{
-            Some(ActionType::Click(point))
-        }
-        else if input == "chord"{
-            Some(ActionType::Chord(point))
-        }
-        else if input == "complete"{
-            Some(ActionType::Complete(point))
-        }
-        else if input == "flag"{
-            Some(ActionType::Flag(point))
+// Everything on the line or an error about the first thing that didn't make sense,
+// semicolons between commands are optional
+fn parse_line(line: &str) -> Result<Vec<Request>, String> {
+    let mut tokens = line.split(|c: char| c.is_whitespace() || c == ';')
+        .filter(|token| !token.is_empty())
+        .peekable();
+    let mut requests = vec![];
+    while let Some(word) = tokens.next() {
+        let mut move_at = |action: fn(Point) -> ActionType| -> Result<Request, String> {
+            Ok(Request::Command(Command::Move(action(parse_point(word, &mut tokens)?))))
+        };
+        let request = match word.to_lowercase().as_str() {
+            "click" | "c" | "open" => move_at(ActionType::Click)?,
+            "flag" | "f" => move_at(ActionType::Flag)?,
+            "chord" | "ch" => move_at(ActionType::Chord)?,
+            "complete" | "a" => move_at(ActionType::Complete)?,
+            "undo" | "u" => Request::Command(Command::Undo),
+            "hint" => Request::Command(Command::Hint),
+            "probabilities" | "probs" | "p" => Request::Command(Command::Probabilities),
+            "save" => Request::Command(Command::Save(parse_file(word, &mut tokens)?)),
+            "load" => Request::Command(Command::Load(parse_file(word, &mut tokens)?)),
+            "new" | "n" => Request::Command(Command::New),
+            "quit" | "q" | "exit" => Request::Command(Command::Quit),
+            "help" | "h" | "?" => Request::Help,
+            _ => return Err(format!("don't know \"{}\", try help", word))
+        };
+        requests.push(request);
+    }
+    Ok(requests)
+}
+
+// a single move, as ActionType prints it
+pub(crate) fn parse_action(text: &str) -> Result<ActionType, String> {
+    match parse_line(text)?.as_slice() {
+        [Request::Command(Command::Move(action))] => Ok(*action),
+        _ => Err(format!("\"{}\" is not a move", text))
+    }
+}
+
+fn parse_file<'a>(command: &str, tokens: &mut impl Iterator<Item=&'a str>) -> Result<String, String> {
+    tokens.next().map(String::from).ok_or_else(|| format!("{} needs a file name", command))
+}
+
+// ROW COLUMN [LAYER] or B7 [LAYER], where A is column 0 and Z is followed by AA
+fn parse_point<'a, I: Iterator<Item=&'a str>>(command: &str, tokens: &mut Peekable<I>) -> Result<Point, String> {
+    let first = tokens.next().ok_or_else(|| format!("{} needs a cell", command))?;
+    let (row, column) = if first.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        let split = first.find(|c: char| !c.is_ascii_alphabetic()).ok_or_else(|| format!("{} needs a row number", first))?;
+        let (letters, digits) = first.split_at(split);
+        (parse_number(digits)?, parse_column(letters)?)
+    } else {
+        let column = tokens.next().ok_or_else(|| format!("{} needs a column after the row", command))?;
+        (parse_number(first)?, parse_number(column)?)
+    };
+    // a bare number after the cell is its layer, anything else is the next command
+    let layer = match tokens.peek() {
+        Some(token) if token.starts_with(|c: char| c.is_ascii_digit()) => parse_number(tokens.next().unwrap_or_default())?,
+        _ => 0
+    };
+    Ok(Point(row, column, layer))
+}
+
+fn parse_number(token: &str) -> Result<usize, String> {
+    token.parse().map_err(|_| {
+        if !token.is_empty() && token.chars().all(|c| c.is_ascii_digit()) {
+            format!("{} is too big", token)
+        } else {
+            format!("{} is not a number", token)
         }
-        else {
-            None
+    })
+}
+
+fn parse_column(letters: &str) -> Result<usize, String> {
+    let value = letters.to_ascii_lowercase().bytes()
+        .try_fold(0usize, |value, letter| value.checked_mul(26)?.checked_add((letter - b'a') as usize + 1))
+        .ok_or_else(|| format!("column {} is too big", letters))?;
+    Ok(value - 1)
+}
+
+#[cfg(test)]
+mod interaction_tests {
+    use super::*;
+
+    fn commands(line: &str) -> Vec<Command> {
+        parse_line(line).unwrap().into_iter()
+            .map(|request| match request {
+                Request::Command(command) => command,
+                Request::Help => panic!("unexpected help")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn moves_and_aliases() {
+        assert_eq!(commands("click 3 4"), vec![Command::Move(ActionType::Click(Point(3, 4, 0)))]);
+        assert_eq!(commands("C 3 4 2"), vec![Command::Move(ActionType::Click(Point(3, 4, 2)))]);
+        assert_eq!(commands("f 0 1"), vec![Command::Move(ActionType::Flag(Point(0, 1, 0)))]);
+        assert_eq!(commands("ch 5 5"), vec![Command::Move(ActionType::Chord(Point(5, 5, 0)))]);
+        assert_eq!(commands("complete 1 2"), vec![Command::Move(ActionType::Complete(Point(1, 2, 0)))]);
+    }
+
+    #[test]
+    fn chess_coordinates() {
+        assert_eq!(commands("c B7"), vec![Command::Move(ActionType::Click(Point(7, 1, 0)))]);
+        assert_eq!(commands("c a0 1"), vec![Command::Move(ActionType::Click(Point(0, 0, 1)))]);
+        assert_eq!(commands("c AA3"), vec![Command::Move(ActionType::Click(Point(3, 26, 0)))]);
+    }
+
+    #[test]
+    fn several_per_line() {
+        assert_eq!(commands("c 3 4 f B2; undo;hint p"), vec![
+            Command::Move(ActionType::Click(Point(3, 4, 0))),
+            Command::Move(ActionType::Flag(Point(2, 1, 0))),
+            Command::Undo,
+            Command::Hint,
+            Command::Probabilities
+        ]);
+        assert_eq!(parse_line("c 1 1 help").unwrap().pop(), Some(Request::Help));
+    }
+
+    #[test]
+    fn meta_commands() {
+        assert_eq!(commands("save game.txt load other.txt new quit"), vec![
+            Command::Save(String::from("game.txt")),
+            Command::Load(String::from("other.txt")),
+            Command::New,
+            Command::Quit
+        ]);
+    }
+
+    #[test]
+    fn actions_print_as_commands() {
+        for action in [ActionType::Click(Point(3, 4, 0)), ActionType::Flag(Point(1, 2, 3)), ActionType::Complete(Point(0, 0, 0))].iter() {
+            assert_eq!(commands(&action.to_string()), vec![Command::Move(*action)]);
         }
     }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        assert!(parse_line("explode 1 2").is_err());
+        assert!(parse_line("click").is_err());
+        assert!(parse_line("click 3").is_err());
+        assert!(parse_line("click B").is_err());
+        assert!(parse_line("click 99999999999999999999999 1").unwrap_err().contains("too big"));
+        assert!(parse_line("click ZZZZZZZZZZZZZZZZZZZZ1").unwrap_err().contains("too big"));
+        assert!(parse_line("save").is_err());
+    }
 }
@@ -0,0 +1,269 @@
+use std::fmt;
+
+// Just enough JSON for the line protocol: values, a parser and compact printing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // keys in the order they were written
+    Object(Vec<(String, Json)>)
+}
+
+// deeper than this is someone trying to blow the stack
+const MAX_DEPTH: usize = 64;
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(key, value)| (String::from(key), value)).collect())
+    }
+
+    pub fn string(text: &str) -> Json {
+        Json::String(String::from(text))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(text) => Some(text),
+            _ => None
+        }
+    }
+
+    // whole numbers only, and only those an f64 holds exactly
+    pub fn as_usize(&self) -> Option<usize> {
+        match *self {
+            Json::Number(number) if number >= 0.0 && number.fract() == 0.0 && number <= 9007199254740992.0 =>
+                Some(number as usize),
+            _ => None
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser{chars: text.chars().collect(), position: 0};
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        if parser.position < parser.chars.len() {
+            return Err(format!("unexpected {:?} after the value", parser.chars[parser.position]))
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) if !number.is_finite() => write!(f, "null"),
+            Json::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => write!(f, "{}", *number as i64),
+            Json::Number(number) => write!(f, "{}", number),
+            Json::String(text) => write_string(f, text),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize
+}
+
+impl Parser {
+    fn skip_whitespace(&mut self) {
+        while self.position < self.chars.len() && self.chars[self.position].is_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = *self.chars.get(self.position).ok_or("the line ends too soon")?;
+        self.position += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), String> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(format!("expected {}", word))
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err(String::from("nested too deep"))
+        }
+        self.skip_whitespace();
+        match self.chars.get(self.position) {
+            None => Err(String::from("expected a value")),
+            Some('n') => self.expect("null").map(|_| Json::Null),
+            Some('t') => self.expect("true").map(|_| Json::Bool(true)),
+            Some('f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.position += 1;
+                let mut values = vec![];
+                self.skip_whitespace();
+                if self.chars.get(self.position) == Some(&']') {
+                    self.position += 1;
+                    return Ok(Json::Array(values))
+                }
+                loop {
+                    values.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Ok(Json::Array(values)),
+                        c => return Err(format!("expected , or ] but got {:?}", c))
+                    }
+                }
+            },
+            Some('{') => {
+                self.position += 1;
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.chars.get(self.position) == Some(&'}') {
+                    self.position += 1;
+                    return Ok(Json::Object(fields))
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.chars.get(self.position) != Some(&'"') {
+                        return Err(String::from("expected a key"))
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.next()? != ':' {
+                        return Err(String::from("expected : after a key"))
+                    }
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Ok(Json::Object(fields)),
+                        c => return Err(format!("expected , or }} but got {:?}", c))
+                    }
+                }
+            },
+            Some(_) => self.number()
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        while self.position < self.chars.len() && "+-0123456789.eE".contains(self.chars[self.position]) {
+            self.position += 1;
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        match text.parse::<f64>() {
+            Ok(number) if number.is_finite() => Ok(Json::Number(number)),
+            _ if text.is_empty() => Err(format!("unexpected {:?}", self.chars[start])),
+            _ => Err(format!("{} is not a number", text))
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        // the opening quote
+        self.position += 1;
+        let mut text = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(text),
+                '\\' => match self.next()? {
+                    '"' => text.push('"'),
+                    '\\' => text.push('\\'),
+                    '/' => text.push('/'),
+                    'b' => text.push('\u{8}'),
+                    'f' => text.push('\u{c}'),
+                    'n' => text.push('\n'),
+                    'r' => text.push('\r'),
+                    't' => text.push('\t'),
+                    'u' => {
+                        let digits: String = (0..4).map(|_| self.next()).collect::<Result<_, _>>()?;
+                        let code = u32::from_str_radix(&digits, 16).map_err(|_| format!("bad escape \\u{}", digits))?;
+                        // surrogate pairs aren't worth it here
+                        text.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                    },
+                    c => return Err(format!("bad escape \\{}", c))
+                },
+                c => text.push(c)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = r#"{"type":"state","cells":[[1,"?",null],[]],"ok":true,"p":0.25,"quote":"a\"b\\c\n"}"#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(value.to_string(), text);
+        assert_eq!(value.get("type").and_then(Json::as_str), Some("state"));
+        assert_eq!(Json::parse(" { \"row\" : 3 } ").unwrap().get("row").and_then(Json::as_usize), Some(3));
+    }
+
+    #[test]
+    fn numbers_have_to_fit() {
+        assert_eq!(Json::parse("-1").unwrap().as_usize(), None);
+        assert_eq!(Json::parse("2.5").unwrap().as_usize(), None);
+        assert_eq!(Json::parse("1e300").unwrap().as_usize(), None);
+        assert!(Json::parse("1e999").is_err());
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        for text in ["", "{", "{\"a\" 1}", "[1,]", "tru", "\"open", "{} x", "@"].iter() {
+            assert!(Json::parse(text).is_err(), "{}", text);
+        }
+        assert!(Json::parse(&"[".repeat(1000)).is_err());
+    }
+}
@@ -3,14 +3,36 @@
 extern crate proptest;
 
 pub mod board;
+pub mod clock;
 pub mod ai;
 pub mod interaction;
 pub mod constraint;
+pub mod dimacs;
+pub mod sat;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod tui;
+pub mod render;
+pub mod hint;
+pub mod protocol;
+pub mod game;
+pub mod session;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+// the protocol and the server's JSON, not part of the library's API
+pub(crate) mod json;
 
 use board::Point;
+use clock::Instant;
+use session::Session;
+use std::fmt;
+use std::fs;
+use std::time::Duration;
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ActionType {
     Click(Point),
     Chord(Point),
@@ -18,35 +40,306 @@ pub enum ActionType {
     Flag(Point)
 }
 
+impl ActionType {
+    pub fn point(&self) -> Point {
+        match *self {
+            ActionType::Click(point) | ActionType::Chord(point) | ActionType::Complete(point) | ActionType::Flag(point) => point
+        }
+    }
+}
+
+// written the way HumanAgent reads them back
+impl fmt::Display for ActionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, point) = match self {
+            ActionType::Click(point) => ("click", point),
+            ActionType::Chord(point) => ("chord", point),
+            ActionType::Complete(point) => ("complete", point),
+            ActionType::Flag(point) => ("flag", point)
+        };
+        write!(f, "{} {} {}", name, point.0, point.1)?;
+        if point.2 > 0 {
+            write!(f, " {}", point.2)?;
+        }
+        Ok(())
+    }
+}
+
+// Everything an agent can ask the game loop for. Besides moves these are the
+// things a person wants that only the loop can do, like taking a move back
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Move(ActionType),
+    Undo,
+    // suggest a move
+    Hint,
+    // show how likely each cell is to hold a mine
+    Probabilities,
+    Save(String),
+    Load(String),
+    // deal a new board with the same settings
+    New,
+    Quit
+}
+
 pub trait Agent {
     fn generate_move(&mut self, board: &board::Board) -> ActionType;
+
+    // agents that want more than moves replace this
+    fn next_command(&mut self, board: &board::Board) -> Command {
+        Command::Move(self.generate_move(board))
+    }
+
+    // the loop swapped the board for another one (undo, load, new game),
+    // anything remembered about the old one is stale
+    fn reset(&mut self) {}
+
+    // how long the game has been played, resumed games included, told before
+    // every show_board. Agents with a clock on screen replace this
+    fn show_clock(&mut self, _elapsed: Duration) {}
+
+    // agents that draw the game themselves replace these
+    fn show_board(&mut self, board: &board::Board) {
+        println!("{}", board);
+    }
+
+    fn show_probabilities(&mut self, board: &board::Board, probabilities: &[(Point, f32)]) {
+        println!("{}", board.to_string_with_probabilities(probabilities));
+    }
+
+    fn show_message(&mut self, message: &str) {
+        println!("{}", message);
+    }
+
+    // something the agent asked for that couldn't be done
+    fn show_error(&mut self, message: &str) {
+        self.show_message(message);
+    }
+
+    fn show_result(&mut self, board: &board::Board, won: bool) {
+        println!("{}", board);
+        println!("{}", if won { "you win!" } else { "you lose" });
+    }
 }
 
 pub fn game_loop(agent: &mut impl Agent, board: &mut board::Board){
-    while !board.is_won(){
-        println!("{}", board);
-        let mines = match agent.generate_move(board) {
-            ActionType::Click(point) => {
-                board.probe(&point)
-            }
-            ActionType::Flag(point) => {
-                board.toggle_flag(&point);
-                0
-            }
-            ActionType::Complete(point) => {
-                board.flag_neighbors(&point);
-                0
-            }
-            ActionType::Chord(point) => {
-                board.chord(&point)
+    let mut session = Session::new(board.clone());
+    play_session(agent, &mut session);
+    *board = session.board;
+}
+
+// game_loop for a game that may already be under way, keeps its history and clock going
+pub fn play_session(agent: &mut impl Agent, session: &mut Session){
+    // time only counts while the game is open
+    let mut resumed = Instant::now();
+    let mut before = session.elapsed();
+    while !session.board.is_won(){
+        agent.show_clock(before + resumed.elapsed());
+        agent.show_board(&session.board);
+        let command = agent.next_command(&session.board);
+        session.set_elapsed(before + resumed.elapsed());
+        let action = match command {
+            Command::Move(action) => action,
+            Command::Quit => return,
+            command => {
+                if run_meta_command(agent, session, command) {
+                    agent.reset();
+                    resumed = Instant::now();
+                    before = session.elapsed();
+                }
+                continue
             }
         };
+        if !session.board.size.is_playable(&action.point()) {
+            agent.show_error(&format!("can't {}, that cell is not on the board", action));
+            continue
+        }
+        let dealing = !session.board.initialized;
+        let mines = session.play(action);
+        if dealing {
+            tell_about_the_deal(agent, &session.board);
+        }
         if mines > 0 {
-            println!("{}", board);
-            println!("you lose");
+            agent.show_result(&session.board, false);
             return
         }
     }
-    println!("{}", board);
-    println!("you win!");
+    agent.show_result(&session.board, true);
+}
+
+// the first click got less protection than asked for, the board was too dense where it landed
+fn tell_about_the_deal(agent: &mut impl Agent, board: &board::Board) {
+    if let Some(dealt) = board.dealt_with() {
+        if dealt != board.first_click_policy() {
+            agent.show_message(&format!("too many mines to keep the first click {}, it was only {}",
+                                        board.first_click_policy().name(), dealt.name()));
+        }
+    }
+}
+
+// plays the move and returns how many mines it set off
+pub(crate) fn make_move(board: &mut board::Board, action: ActionType) -> usize {
+    match action {
+        ActionType::Click(point) => board.probe(&point),
+        ActionType::Flag(point) => {
+            board.toggle_flag(&point);
+            0
+        },
+        ActionType::Complete(point) => {
+            board.flag_neighbors(&point);
+            0
+        },
+        ActionType::Chord(point) => board.chord(&point)
+    }
+}
+
+// everything but moves and quitting, says whether the board was swapped for another
+fn run_meta_command(agent: &mut impl Agent, session: &mut Session, command: Command) -> bool {
+    match command {
+        Command::Undo => {
+            let undone = session.undo();
+            if !undone {
+                agent.show_error("nothing to undo");
+            }
+            undone
+        },
+        Command::Hint => {
+            match hint::hint(&session.board) {
+                Some(hint) => agent.show_message(&hint.to_string()),
+                None => agent.show_message("nothing left to do")
+            }
+            false
+        },
+        Command::Probabilities => {
+            let probabilities = ai::NaiveAI::new(0, 1000).with_verbose(false).mine_probabilities(&session.board);
+            agent.show_probabilities(&session.board, &probabilities);
+            false
+        },
+        Command::Save(path) => {
+            match fs::write(&path, session.to_save_string()) {
+                Ok(()) => agent.show_message(&format!("saved to {} after {}", path, session::clock_time(session.elapsed()))),
+                Err(error) => agent.show_error(&format!("can't save to {}: {}", path, error))
+            }
+            false
+        },
+        Command::Load(path) => {
+            let loaded = fs::read_to_string(&path)
+                .map_err(|error| error.to_string())
+                .and_then(|text| Session::from_save_string(&text).map_err(|error| error.to_string()));
+            match loaded {
+                Ok(loaded) => {
+                    *session = loaded;
+                    agent.show_message(&format!("loaded {}, {} played", path, session::clock_time(session.elapsed())));
+                    true
+                },
+                Err(error) => {
+                    agent.show_error(&format!("can't load {}: {}", path, error));
+                    false
+                }
+            }
+        },
+        Command::New => {
+            *session = Session::new(session.board.new_game());
+            true
+        },
+        Command::Move(_) | Command::Quit => false
+    }
+}
+
+#[cfg(test)]
+mod loop_tests {
+    use super::*;
+    use board::Board;
+
+    // plays back a script and remembers what it saw
+    struct ScriptedAgent {
+        script: Vec<Command>,
+        resets: usize,
+        messages: Vec<String>,
+        clocks: Vec<Duration>
+    }
+
+    impl Agent for ScriptedAgent {
+        fn generate_move(&mut self, _board: &Board) -> ActionType {
+            unreachable!()
+        }
+
+        fn next_command(&mut self, _board: &Board) -> Command {
+            if self.script.is_empty() { Command::Quit } else { self.script.remove(0) }
+        }
+
+        fn reset(&mut self) {
+            self.resets += 1;
+        }
+
+        fn show_clock(&mut self, elapsed: Duration) {
+            self.clocks.push(elapsed);
+        }
+
+        fn show_board(&mut self, _board: &Board) {}
+
+        fn show_message(&mut self, message: &str) {
+            self.messages.push(String::from(message));
+        }
+    }
+
+    #[test]
+    fn undo_and_new_replace_the_board() {
+        let mut board = Board::new_from_ints(9, 9, 10).unwrap();
+        let unmined = Point(0, 0, 0);
+        let script = vec![
+            Command::Move(ActionType::Click(unmined)),
+            Command::Move(ActionType::Flag(Point(8, 8, 0))),
+            Command::Undo,
+            Command::Hint
+        ];
+        let mut agent = ScriptedAgent{script, resets: 0, messages: vec![], clocks: vec![]};
+        game_loop(&mut agent, &mut board);
+        assert!(board.initialized);
+        assert!(!board.retrieve_cell(&Point(8, 8, 0)).knowledge.is_flag());
+        assert_eq!(agent.resets, 1);
+        assert!(agent.messages[0].starts_with("click ") || agent.messages[0].starts_with("flag "));
+
+        let script = vec![Command::Undo, Command::Undo, Command::New];
+        let mut agent = ScriptedAgent{script, resets: 0, messages: vec![], clocks: vec![]};
+        game_loop(&mut agent, &mut board);
+        assert!(!board.initialized);
+        assert_eq!(agent.messages, vec![String::from("nothing to undo"), String::from("nothing to undo")]);
+        assert_eq!(agent.resets, 1);
+    }
+
+    #[test]
+    fn weaker_first_clicks_are_reported() {
+        let mut board = Board::new_from_ints(5, 5, 16).unwrap();
+        let mut agent = ScriptedAgent{script: vec![Command::Move(ActionType::Click(Point(2, 2, 0)))], resets: 0, messages: vec![], clocks: vec![]};
+        game_loop(&mut agent, &mut board);
+        assert_eq!(agent.messages, vec![String::from("too many mines to keep the first click safe-radius 2, it was only safe-radius 1")]);
+    }
+
+    #[test]
+    fn saved_games_resume_with_their_history() {
+        let path = std::env::temp_dir().join(format!("minesweeper-resume-{}.txt", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let mut board = Board::new_from_ints(9, 9, 10).unwrap();
+        let script = vec![
+            Command::Move(ActionType::Click(Point(4, 4, 0))),
+            Command::Move(ActionType::Flag(Point(0, 0, 0))),
+            Command::Save(path.clone())
+        ];
+        let mut agent = ScriptedAgent{script, resets: 0, messages: vec![], clocks: vec![]};
+        game_loop(&mut agent, &mut board);
+        assert!(agent.messages[0].starts_with("saved to "), "{:?}", agent.messages);
+
+        let mut session = Session::from_save_string(&fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(session.board.to_save_string(), board.to_save_string());
+        session.set_elapsed(Duration::from_secs(600));
+        let mut agent = ScriptedAgent{script: vec![Command::Undo, Command::Undo, Command::Undo], resets: 0, messages: vec![], clocks: vec![]};
+        play_session(&mut agent, &mut session);
+        // the clock carries on from the save
+        assert!(agent.clocks.iter().all(|clock| *clock >= Duration::from_secs(600)));
+        assert_eq!(agent.resets, 2);
+        assert_eq!(agent.messages, vec![String::from("nothing to undo")]);
+        assert_eq!(session.board.get_unknown_points().len(), 81);
+    }
 }
@@ -1,11 +1,204 @@
-use minesweeper;
+use std::env;
+use std::time::Duration;
+use minesweeper::board::Board;
+use minesweeper::board::BoardSize;
+use minesweeper::board::Difficulty;
+use minesweeper::board::Point;
+use minesweeper::dimacs::MineEncoding;
+use minesweeper::interaction::HumanAgent;
+use minesweeper::protocol::ProtocolAgent;
+use minesweeper::render::Renderer;
+use minesweeper::render::Theme;
+use minesweeper::server::GameServer;
+use minesweeper::session;
+use minesweeper::session::Session;
+use minesweeper::tui::TerminalAgent;
+
+const USAGE: &str = "usage: minesweeper [--level LEVEL] [--density DENSITY] [--theme THEME] [--heatmap] [--tui | --human | --protocol | --bot COMMAND] [--trace FILE] [--resume FILE] [dimacs [SOLVER] | --serve ADDRESS]
+LEVEL is beginner (the default), intermediate, expert or WIDTHxHEIGHT/MINES.
+DENSITY replaces the level's mine count with that fraction of the board.
+THEME is ascii, unicode (the default), color or colorblind, --heatmap colours the AI's
+probabilities by risk instead of drawing them as glyphs (color themes only).
+--tui plays the game yourself in a full-screen terminal UI instead of watching the AI,
+--human plays it by typing commands.
+--protocol speaks the JSON lines bot protocol on stdin and stdout, --bot runs COMMAND
+(split on spaces) and lets it play over the same protocol, with 10 seconds per move.
+--trace writes how the AI came to each move to FILE.
+--resume carries on with a game saved with save FILE, the level and density don't apply.
+--serve hosts games over HTTP on ADDRESS, for example 127.0.0.1:8080.";
 
 fn main() {
-    let width = 9;
-    let height = 9;
-    let mine_count = 30;
-    let mut board = minesweeper::board::Board::new_from_ints(width, height, mine_count).expect("no board!");
-    let mut agent = minesweeper::ai::NaiveAI::new(10, 1000);
-    //let mut agent = minesweeper::interaction::HumanAgent{};
-    minesweeper::game_loop(&mut agent, &mut board);
+    let mut args = env::args().skip(1);
+    let mut difficulty = Difficulty::Beginner;
+    let mut density: Option<f64> = None;
+    let mut theme: Option<Theme> = None;
+    let mut heatmap = false;
+    let mut tui = false;
+    let mut human = false;
+    let mut protocol = false;
+    let mut bot: Option<String> = None;
+    let mut trace: Option<String> = None;
+    let mut serve: Option<String> = None;
+    let mut resume: Option<String> = None;
+    let mut rest: Vec<String> = vec![];
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--level" => match args.next().as_deref().and_then(Difficulty::from_name) {
+                Some(level) => difficulty = level,
+                None => return eprintln!("{}", USAGE)
+            },
+            "--density" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(value) => density = Some(value),
+                None => return eprintln!("{}", USAGE)
+            },
+            "--theme" => match args.next().as_deref().and_then(Theme::from_name) {
+                Some(value) => theme = Some(value),
+                None => return eprintln!("{}", USAGE)
+            },
+            "--heatmap" => heatmap = true,
+            "--tui" => tui = true,
+            "--human" => human = true,
+            "--protocol" => protocol = true,
+            "--bot" => match args.next() {
+                Some(command) => bot = Some(command),
+                None => return eprintln!("{}", USAGE)
+            },
+            "--trace" => match args.next() {
+                Some(path) => trace = Some(path),
+                None => return eprintln!("{}", USAGE)
+            },
+            "--serve" => match args.next() {
+                Some(address) => serve = Some(address),
+                None => return eprintln!("{}", USAGE)
+            },
+            "--resume" => match args.next() {
+                Some(path) => resume = Some(path),
+                None => return eprintln!("{}", USAGE)
+            },
+            _ => rest.push(arg)
+        }
+    }
+    if let Some(address) = serve {
+        let workers = std::thread::available_parallelism().map_or(4, |count| count.get());
+        match GameServer::new().listen(&address, workers) {
+            Ok(listener) => {
+                eprintln!("serving games on http://{}", listener.address());
+                listener.wait()
+            },
+            Err(error) => eprintln!("can't serve on {}: {}", address, error)
+        }
+        return
+    }
+    let board = match density {
+        None => Board::new_from_difficulty(difficulty),
+        Some(density) => Board::new_with_density(BoardSize::new(difficulty.width(), difficulty.height()), density)
+    };
+    let board = match board {
+        Ok(board) => board,
+        Err(error) => {
+            eprintln!("can't make the board: {}", error);
+            return
+        }
+    };
+    let mut session = match resume {
+        None => Session::new(board),
+        Some(path) => {
+            let loaded = std::fs::read_to_string(&path)
+                .map_err(|error| error.to_string())
+                .and_then(|text| Session::from_save_string(&text).map_err(|error| error.to_string()));
+            match loaded {
+                Ok(session) => {
+                    eprintln!("resuming {}, {} played", path, session::clock_time(session.elapsed()));
+                    session
+                },
+                Err(error) => return eprintln!("can't resume {}: {}", path, error)
+            }
+        }
+    };
+    if rest.first().map(String::as_str) == Some("dimacs") {
+        export_dimacs(&mut session.board, rest.get(1));
+        return
+    }
+    if tui {
+        match TerminalAgent::new() {
+            Ok(agent) => {
+                let mut agent = match theme {
+                    Some(theme) => agent.with_renderer(Renderer::new(theme).with_heatmap(heatmap)),
+                    None => agent
+                };
+                minesweeper::play_session(&mut agent, &mut session)
+            },
+            Err(error) => eprintln!("can't start the terminal UI: {}", error)
+        }
+        return
+    }
+    if human {
+        minesweeper::play_session(&mut HumanAgent::new(), &mut session);
+        return
+    }
+    if protocol {
+        minesweeper::play_session(&mut ProtocolAgent::stdio(), &mut session);
+        return
+    }
+    if let Some(command) = bot {
+        let mut words = command.split_whitespace().map(String::from);
+        let program = match words.next() {
+            Some(program) => program,
+            None => return eprintln!("{}", USAGE)
+        };
+        let args: Vec<String> = words.collect();
+        match ProtocolAgent::spawn(&program, &args) {
+            Ok(agent) => {
+                let mut agent = agent.with_timeout(Duration::from_secs(10));
+                minesweeper::play_session(&mut agent, &mut session);
+                println!("{}", session.board);
+            },
+            Err(error) => eprintln!("can't start {}: {}", program, error)
+        }
+        return
+    }
+    let renderer = Renderer::new(theme.unwrap_or(Theme::Unicode)).with_heatmap(heatmap);
+    let mut agent = minesweeper::ai::NaiveAI::new(10, 1000).with_renderer(renderer).with_trace(trace.is_some());
+    minesweeper::play_session(&mut agent, &mut session);
+    if let Some(path) = trace {
+        let mut lines = agent.trace().join("\n");
+        lines.push('\n');
+        if let Err(error) = std::fs::write(&path, lines) {
+            eprintln!("can't write the trace to {}: {}", path, error);
+        }
+    }
+}
+
+// usage: minesweeper dimacs [SOLVER]
+// makes the opening click and prints the frontier as DIMACS CNF, or hands it to SOLVER
+fn export_dimacs(board: &mut Board, solver: Option<&String>) {
+    board.probe(&Point(0, 0, 0));
+    eprintln!("{}", board);
+    let encoding = MineEncoding::from_board(board);
+    match solver {
+        None => print!("{}", encoding.to_dimacs()),
+        Some(solver) => {
+            // one file per run, so runs side by side don't overwrite each other's
+            let path = env::temp_dir().join(format!("minesweeper-{}.cnf", std::process::id()));
+            let solved = encoding.solve_with(solver, &path);
+            let _ = std::fs::remove_file(&path);
+            let solved = match solved {
+                Ok(solved) => solved,
+                Err(error) => {
+                    // a non-zero status so scripts don't read the missing board as no model
+                    eprintln!("can't solve with {}: {}", solver, error);
+                    std::process::exit(1)
+                }
+            };
+            match solved {
+                None => println!("solver found no model"),
+                Some(assignments) => {
+                    let probabilities: Vec<(Point, f32)> = assignments.into_iter()
+                        .map(|(point, mines)| (point, if mines > 0 { 1.0 } else { 0.0 }))
+                        .collect();
+                    println!("{}", board.to_string_with_probabilities(&probabilities));
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,364 @@
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::process::Child;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use super::board::Board;
+use super::board::Content;
+use super::board::KnowledgeState;
+use super::board::Lattice;
+use super::board::Point;
+use super::board::Topology;
+use super::json::Json;
+use super::Agent;
+use super::ActionType;
+use super::Command;
+
+// The line protocol for bots, one JSON object per line each way.
+//
+// The game sends
+//   {"type":"state", "width", "height", "depth", "lattice", "topology", "mines", "mines_per_cell",
+//    "remaining_mines", "initialized", "cells"}   before every move, cells[layer][row][column] is
+//      a number once revealed, "?" unknown, "F" flagged ("F2" for two flags), "*" a revealed mine
+//      and null for holes in the board
+//   {"type":"error", "message"}       the last line couldn't be used, send another
+//   {"type":"message", "text"}        answers to hint and the like
+//   {"type":"probabilities", "cells": [{"row", "column", "layer", "probability"}]}
+//   {"type":"result", "won"}          the game is over
+// and reads back
+//   {"action":"click"|"flag"|"chord"|"complete", "row", "column", "layer" (optional)}
+//   {"action":"undo"|"hint"|"probabilities"|"new"|"quit"}
+//   {"action":"save"|"load", "file"}
+pub struct ProtocolAgent {
+    // lines from the bot, the sender goes away when the bot stops talking
+    input: mpsc::Receiver<String>,
+    output: Box<dyn Write + Send>,
+    // how long the bot gets for each line, forever if None
+    timeout: Option<Duration>,
+    child: Option<Child>
+}
+
+impl ProtocolAgent {
+    pub fn new(input: impl BufRead + Send + 'static, output: impl Write + Send + 'static) -> ProtocolAgent {
+        let (sender, receiver) = mpsc::channel();
+        // reading blocks, so it happens off to the side where a timeout can't get stuck behind it
+        thread::spawn(move || {
+            for line in input.lines() {
+                match line {
+                    Ok(line) => if sender.send(line).is_err() { return },
+                    Err(_) => return
+                }
+            }
+        });
+        ProtocolAgent{input: receiver, output: Box::new(output), timeout: None, child: None}
+    }
+
+    // speak the protocol on this process's own stdin and stdout
+    pub fn stdio() -> ProtocolAgent {
+        ProtocolAgent::new(BufReader::new(io::stdin()), io::stdout())
+    }
+
+    // run a bot and speak the protocol on its stdin and stdout, its stderr is left alone
+    pub fn spawn(program: &str, args: &[String]) -> io::Result<ProtocolAgent> {
+        let mut child = std::process::Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin was piped");
+        let stdout = child.stdout.take().expect("stdout was piped");
+        let mut agent = ProtocolAgent::new(BufReader::new(stdout), stdin);
+        agent.child = Some(child);
+        Ok(agent)
+    }
+
+    // a bot that takes longer than this for a line forfeits the game
+    pub fn with_timeout(mut self, timeout: Duration) -> ProtocolAgent {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn send(&mut self, message: Json) {
+        // a bot that stopped listening shows up as a closed input soon enough
+        let _ = writeln!(self.output, "{}", message).and_then(|_| self.output.flush());
+    }
+
+    fn receive(&mut self) -> Result<String, String> {
+        match self.timeout {
+            None => self.input.recv().map_err(|_| String::from("the bot hung up")),
+            Some(timeout) => self.input.recv_timeout(timeout).map_err(|error| match error {
+                mpsc::RecvTimeoutError::Timeout => format!("no answer within {:?}", timeout),
+                mpsc::RecvTimeoutError::Disconnected => String::from("the bot hung up")
+            })
+        }
+    }
+}
+
+impl Drop for ProtocolAgent {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Agent for ProtocolAgent {
+    fn generate_move(&mut self, board: &Board) -> ActionType {
+        loop {
+            match self.next_command(board) {
+                Command::Move(action) => return action,
+                // there's no way out of a move, a bot that quits or goes quiet forfeits
+                Command::Quit => return forfeit(board),
+                _ => self.show_error("only moves here")
+            }
+        }
+    }
+
+    fn next_command(&mut self, board: &Board) -> Command {
+        self.send(player_view(board));
+        loop {
+            let line = match self.receive() {
+                Ok(line) => line,
+                Err(problem) => {
+                    self.show_error(&format!("{}, giving up", problem));
+                    return Command::Quit
+                }
+            };
+            if line.trim().is_empty() {
+                continue
+            }
+            match Json::parse(&line)
+                .map_err(|problem| format!("not JSON: {}", problem))
+                .and_then(|message| command_from_json(&message)) {
+                Ok(command) => return command,
+                Err(problem) => self.show_error(&problem)
+            }
+        }
+    }
+
+    fn show_board(&mut self, _board: &Board) {}
+
+    fn show_probabilities(&mut self, _board: &Board, probabilities: &[(Point, f32)]) {
+        self.send(Json::object(vec![("type", Json::string("probabilities")), ("cells", probabilities_json(probabilities))]));
+    }
+
+    fn show_message(&mut self, message: &str) {
+        self.send(Json::object(vec![("type", Json::string("message")), ("text", Json::string(message))]));
+    }
+
+    fn show_error(&mut self, message: &str) {
+        self.send(Json::object(vec![("type", Json::string("error")), ("message", Json::string(message))]));
+    }
+
+    fn show_result(&mut self, board: &Board, won: bool) {
+        self.send(player_view(board));
+        self.send(Json::object(vec![("type", Json::string("result")), ("won", Json::Bool(won))]));
+    }
+}
+
+// Steps on a mine. Before the deal there are none, so it opens a cell and the
+// next move is the one that loses
+fn forfeit(board: &Board) -> ActionType {
+    let points = board.size.points();
+    let mined = points.iter().find(|point| {
+        let cell = board.retrieve_cell(point);
+        !matches!(cell.knowledge, KnowledgeState::Known) && matches!(cell.content, Content::Mine(_))
+    });
+    let point = mined.copied()
+        .or_else(|| board.get_unknown_points().first().copied())
+        .unwrap_or(points[0]);
+    ActionType::Click(point)
+}
+
+// what a player can see, nothing about unrevealed cells
+pub(crate) fn player_view(board: &Board) -> Json {
+    let size = &board.size;
+    let cells = (0..size.depth()).map(|layer| Json::Array((0..size.height()).map(|row| Json::Array((0..size.width()).map(|column| {
+        let point = Point(row, column, layer);
+        if !size.is_playable(&point) {
+            return Json::Null
+        }
+        let cell = board.retrieve_cell(&point);
+        match (&cell.knowledge, &cell.content) {
+            (KnowledgeState::Unknown, _) => Json::string("?"),
+            (KnowledgeState::Flag(1), _) => Json::string("F"),
+            (KnowledgeState::Flag(flags), _) => Json::String(format!("F{}", flags)),
+            (KnowledgeState::Known, Content::Mine(_)) => Json::string("*"),
+            (KnowledgeState::Known, Content::Empty) => Json::Number(cell.clue as f64)
+        }
+    }).collect())).collect())).collect();
+    Json::object(vec![
+        ("type", Json::string("state")),
+        ("width", Json::Number(size.width() as f64)),
+        ("height", Json::Number(size.height() as f64)),
+        ("depth", Json::Number(size.depth() as f64)),
+        ("lattice", Json::string(match size.lattice() {
+            Lattice::Square => "square",
+            Lattice::Hex => "hex"
+        })),
+        ("topology", Json::string(match size.topology() {
+            Topology::Rectangle => "rectangle",
+            Topology::Torus => "torus"
+        })),
+        ("mines", Json::Number(board.mine_count as f64)),
+        ("mines_per_cell", Json::Number(board.mines_per_cell() as f64)),
+        ("remaining_mines", Json::Number(board.remaining_mines() as f64)),
+        ("initialized", Json::Bool(board.initialized)),
+        ("cells", Json::Array(cells))
+    ])
+}
+
+pub(crate) fn probabilities_json(probabilities: &[(Point, f32)]) -> Json {
+    Json::Array(probabilities.iter()
+        .map(|(point, probability)| Json::object(vec![
+            ("row", Json::Number(point.0 as f64)),
+            ("column", Json::Number(point.1 as f64)),
+            ("layer", Json::Number(point.2 as f64)),
+            ("probability", Json::Number(*probability as f64))
+        ]))
+        .collect())
+}
+
+pub(crate) fn command_from_json(message: &Json) -> Result<Command, String> {
+    let action = message.get("action").and_then(Json::as_str).ok_or("expected an object with an \"action\"")?;
+    let coordinate = |name: &str| message.get(name)
+        .ok_or_else(|| format!("{} needs a {}", action, name))?
+        .as_usize()
+        .ok_or_else(|| format!("{} has to be a whole number that isn't negative", name));
+    let point = || -> Result<Point, String> {
+        let layer = match message.get("layer") {
+            None => 0,
+            Some(_) => coordinate("layer")?
+        };
+        Ok(Point(coordinate("row")?, coordinate("column")?, layer))
+    };
+    let file = || message.get("file").and_then(Json::as_str).map(String::from).ok_or_else(|| format!("{} needs a file", action));
+    Ok(match action {
+        "click" => Command::Move(ActionType::Click(point()?)),
+        "flag" => Command::Move(ActionType::Flag(point()?)),
+        "chord" => Command::Move(ActionType::Chord(point()?)),
+        "complete" => Command::Move(ActionType::Complete(point()?)),
+        "undo" => Command::Undo,
+        "hint" => Command::Hint,
+        "probabilities" => Command::Probabilities,
+        "save" => Command::Save(file()?),
+        "load" => Command::Load(file()?),
+        "new" => Command::New,
+        "quit" => Command::Quit,
+        _ => return Err(format!("unknown action {:?}", action))
+    })
+}
+
+#[cfg(test)]
+mod protocol_tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use crate::board::BoardSize;
+
+    // a writer the test can still read after handing it over
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn messages(&self) -> Vec<Json> {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+                .lines()
+                .map(|line| Json::parse(line).unwrap())
+                .collect()
+        }
+    }
+
+    fn kinds(messages: &[Json]) -> Vec<&str> {
+        messages.iter().filter_map(|message| message.get("type").and_then(Json::as_str)).collect()
+    }
+
+    #[test]
+    fn bad_lines_are_errors_not_crashes() {
+        let script = concat!(
+            "not json\n",
+            "{\"action\":\"explode\"}\n",
+            "{\"action\":\"click\",\"row\":-1,\"column\":0}\n",
+            "{\"action\":\"click\",\"row\":50,\"column\":0}\n",
+            "{\"action\":\"click\",\"row\":0,\"column\":0}\n",
+            "{\"action\":\"hint\"}\n",
+            "{\"action\":\"quit\"}\n");
+        let output = Shared::default();
+        let mut agent = ProtocolAgent::new(Cursor::new(script), output.clone());
+        let mut board = Board::new_with_mines(BoardSize::new(4, 4), &[Point(3, 3, 0)]).unwrap();
+        crate::game_loop(&mut agent, &mut board);
+        let messages = output.messages();
+        assert_eq!(kinds(&messages), vec!["state", "error", "error", "error", "error", "state", "state", "message", "state"]);
+        assert!(messages[1].get("message").and_then(Json::as_str).unwrap().starts_with("not JSON"));
+        // the loop turns down the off-board click and asks again
+        assert!(messages[4].get("message").and_then(Json::as_str).unwrap().contains("not on the board"));
+        // the click opened the top left, the mine stays hidden
+        let cells = messages[6].get("cells").unwrap().to_string();
+        assert!(cells.starts_with("[[[0,0,0,0],[0,0,0,0],[0,0,1,1],[0,0,1,\"?\"]]]"), "{}", cells);
+    }
+
+    #[test]
+    fn results_and_hangups() {
+        let output = Shared::default();
+        let mut agent = ProtocolAgent::new(Cursor::new("{\"action\":\"click\",\"row\":0,\"column\":0,\"layer\":0}\n"), output.clone());
+        let board = Board::new_with_mines(BoardSize::new(3, 1), &[Point(0, 2, 0)]).unwrap();
+        crate::game_loop(&mut agent, &mut board.clone());
+        // a second state after the click, then the input runs out
+        let messages = output.messages();
+        assert_eq!(kinds(&messages), vec!["state", "state", "error"]);
+        assert_eq!(messages[1].get("remaining_mines").and_then(Json::as_usize), Some(1));
+
+        // stepping on the mine ends it
+        let output = Shared::default();
+        let mut agent = ProtocolAgent::new(Cursor::new("{\"action\":\"click\",\"row\":0,\"column\":2}\n"), output.clone());
+        crate::game_loop(&mut agent, &mut board.clone());
+        assert_eq!(kinds(&output.messages()).last(), Some(&"result"));
+    }
+
+    #[test]
+    fn quitting_mid_move_forfeits() {
+        let board = Board::new_with_mines(BoardSize::new(3, 1), &[Point(0, 2, 0)]).unwrap();
+        let mut agent = ProtocolAgent::new(Cursor::new("{\"action\":\"hint\"}\n{\"action\":\"quit\"}\n"), Shared::default());
+        assert_eq!(agent.generate_move(&board), ActionType::Click(Point(0, 2, 0)));
+        // hung up, and nothing dealt yet
+        let blank = Board::new_from_ints(9, 9, 10).unwrap();
+        assert_eq!(agent.generate_move(&blank), ActionType::Click(Point(0, 0, 0)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn subprocess_bots() {
+        let board = Board::new_with_mines(BoardSize::new(3, 1), &[Point(0, 2, 0)]).unwrap();
+        // reads the state, opens the left, flags the right
+        let script = "read s; echo '{\"action\":\"click\",\"row\":0,\"column\":0}'; read s; echo '{\"action\":\"flag\",\"row\":0,\"column\":2}'; read s; read s";
+        let mut agent = ProtocolAgent::spawn("sh", &[String::from("-c"), String::from(script)]).unwrap()
+            .with_timeout(Duration::from_secs(10));
+        let mut played = board.clone();
+        crate::game_loop(&mut agent, &mut played);
+        assert!(played.is_won());
+
+        // one that never answers forfeits
+        let mut agent = ProtocolAgent::spawn("sleep", &[String::from("10")]).unwrap()
+            .with_timeout(Duration::from_millis(100));
+        let mut played = board.clone();
+        crate::game_loop(&mut agent, &mut played);
+        assert!(!played.is_won());
+    }
+}
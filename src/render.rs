@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use super::board::Board;
+use super::board::Cell;
+use super::board::ClueNoise;
+use super::board::Content;
+use super::board::KnowledgeState;
+use super::board::Lattice;
+use super::board::Point;
+
+// How cells are drawn. The colour themes use the Unicode glyphs and add ANSI
+// colours on top, the colour-blind one sticks to a blue/orange palette and
+// never tells things apart by colour alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Ascii,
+    Unicode,
+    Color,
+    ColorBlind
+}
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name.to_lowercase().as_str() {
+            "ascii" => Some(Theme::Ascii),
+            "unicode" => Some(Theme::Unicode),
+            "color" | "colour" => Some(Theme::Color),
+            "colorblind" | "colourblind" => Some(Theme::ColorBlind),
+            _ => None
+        }
+    }
+
+    fn is_colored(&self) -> bool {
+        matches!(*self, Theme::Color | Theme::ColorBlind)
+    }
+}
+
+// probability glyphs from certainly safe to certainly mined
+const UNICODE_RISK: [&str; 7] = ["◌", "-", "=", "▤", "▦", "▩", "●"];
+const ASCII_RISK: [&str; 7] = ["o", "-", "=", "+", "%", "&", "@"];
+// heatmap backgrounds, same buckets, as 256 colour palette indices
+const COLOR_RISK: [u8; 7] = [22, 28, 64, 136, 166, 160, 124];
+const COLORBLIND_RISK: [u8; 7] = [17, 25, 31, 137, 172, 208, 202];
+// number colours, index 0 is a 1. Anything past 8 uses the last one
+const COLOR_NUMBERS: [u8; 9] = [21, 28, 196, 18, 88, 30, 240, 248, 201];
+const COLORBLIND_NUMBERS: [u8; 9] = [32, 214, 75, 166, 175, 227, 36, 248, 231];
+
+// what a single cell looks like before padding
+struct Glyph {
+    text: String,
+    foreground: Option<u8>,
+    background: Option<u8>,
+    bold: bool
+}
+
+impl Glyph {
+    fn plain(text: &str) -> Glyph {
+        Glyph{text: String::from(text), foreground: None, background: None, bold: false}
+    }
+
+    fn colored(text: &str, foreground: Option<u8>, bold: bool) -> Glyph {
+        Glyph{text: String::from(text), foreground, background: None, bold}
+    }
+
+    fn padded(&self, width: usize, theme: Theme) -> String {
+        let text = format!("{:>width$}", self.text, width = width);
+        if !theme.is_colored() {
+            return text
+        }
+        // plain 256 colour escapes, anything that draws the board understands them
+        let mut codes = vec![];
+        if let Some(foreground) = self.foreground {
+            codes.push(format!("38;5;{}", foreground));
+        }
+        if let Some(background) = self.background {
+            codes.push(format!("48;5;{}", background));
+        }
+        if self.bold {
+            codes.push(String::from("1"));
+        }
+        if codes.is_empty() {
+            return text
+        }
+        format!("\x1b[{}m{}\x1b[0m", codes.join(";"), text)
+    }
+}
+
+// Turns boards into text. The heatmap colours the background of cells with a
+// probability by how risky they are instead of swapping in a probability
+// glyph, it needs a colour theme and is ignored otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Renderer {
+    theme: Theme,
+    heatmap: bool
+}
+
+impl Default for Renderer {
+    fn default() -> Renderer {
+        Renderer::new(Theme::Unicode)
+    }
+}
+
+impl Renderer {
+    pub fn new(theme: Theme) -> Renderer {
+        Renderer{theme, heatmap: false}
+    }
+
+    pub fn with_heatmap(mut self, heatmap: bool) -> Renderer {
+        self.heatmap = heatmap;
+        self
+    }
+
+    pub fn theme(&self) -> Theme {
+        self.theme
+    }
+
+    // Characters the widest cell can need on this board at any point in the
+    // game, so the layout stays put as numbers get revealed.
+    pub fn glyph_width(&self, board: &Board) -> usize {
+        let most_neighbors = board.size.points().iter()
+            .map(|point| board.neighbor_points(point).len())
+            .max()
+            .unwrap_or(0);
+        let noise = if board.clue_noise() == ClueNoise::Exact { 0 } else { 1 };
+        let clue_width = (most_neighbors * board.mines_per_cell() + noise).to_string().len();
+        let flag_width = match (self.theme, board.mines_per_cell()) {
+            (_, 1) => 1,
+            // "3F"
+            (Theme::Ascii, flags) => flags.to_string().len() + 1,
+            // circled numbers
+            _ => 1
+        };
+        clue_width.max(flag_width)
+    }
+
+    // one cell padded to glyph_width, probability is only drawn on unknown cells
+    pub fn cell(&self, board: &Board, point: &Point, probability: Option<f32>, width: usize) -> String {
+        if !board.size.is_playable(point) {
+            return " ".repeat(width)
+        }
+        let cell = board.retrieve_cell(point);
+        let glyph = match (probability, &cell.knowledge) {
+            (Some(probability), KnowledgeState::Unknown) => self.probability_glyph(cell, probability),
+            _ => self.cell_glyph(cell)
+        };
+        glyph.padded(width, self.theme)
+    }
+
+    fn cell_glyph(&self, cell: &Cell) -> Glyph {
+        let numbers = match self.theme {
+            Theme::ColorBlind => &COLORBLIND_NUMBERS,
+            _ => &COLOR_NUMBERS
+        };
+        match (&cell.knowledge, &cell.content, self.theme) {
+            (KnowledgeState::Flag(flags), _, Theme::Ascii) if *flags > 1 => Glyph::plain(&format!("{}F", flags)),
+            (KnowledgeState::Flag(_), _, Theme::Ascii) => Glyph::plain("F"),
+            (KnowledgeState::Flag(flags), _, theme) => {
+                let text = if *flags == 1 {
+                    String::from("▶")
+                } else {
+                    // circled numbers go up to 20
+                    std::char::from_u32(0x245f + (*flags).min(20) as u32).map_or(String::from("▶"), String::from)
+                };
+                let foreground = if theme == Theme::Color { Some(202) } else { None };
+                Glyph::colored(&text, foreground, true)
+            },
+            (KnowledgeState::Unknown, _, Theme::Ascii) => Glyph::plain("#"),
+            (KnowledgeState::Unknown, _, _) => Glyph::plain("□"),
+            (_, Content::Mine(_), Theme::Ascii) => Glyph::plain("*"),
+            (_, Content::Mine(_), theme) => {
+                let foreground = if theme == Theme::Color { Some(196) } else { None };
+                Glyph::colored("X", foreground, true)
+            },
+            (_, Content::Empty, Theme::Ascii) if cell.clue == 0 => Glyph::plain("."),
+            (_, Content::Empty, theme) if cell.clue == 0 => {
+                let foreground = if theme.is_colored() { Some(240) } else { None };
+                Glyph::colored("_", foreground, false)
+            },
+            (_, Content::Empty, theme) => {
+                let foreground = if theme.is_colored() { Some(numbers[(cell.clue - 1).min(numbers.len() - 1)]) } else { None };
+                Glyph::colored(&cell.clue.to_string(), foreground, false)
+            }
+        }
+    }
+
+    fn probability_glyph(&self, cell: &Cell, probability: f32) -> Glyph {
+        let level = risk_level(probability);
+        match self.theme {
+            Theme::Color | Theme::ColorBlind if self.heatmap => {
+                let backgrounds = if self.theme == Theme::Color { &COLOR_RISK } else { &COLORBLIND_RISK };
+                let mut glyph = self.cell_glyph(cell);
+                glyph.background = Some(backgrounds[level]);
+                glyph
+            },
+            Theme::Ascii => Glyph::plain(ASCII_RISK[level]),
+            _ => Glyph::plain(UNICODE_RISK[level])
+        }
+    }
+
+    pub fn render(&self, board: &Board, probabilities: &[(Point, f32)]) -> String {
+        let probability_lookup: HashMap<Point, f32> = probabilities.iter()
+            .map(|(p, f)| (*p, *f))
+            .collect();
+        let size = &board.size;
+        let width = self.glyph_width(board).max((size.width().max(1) - 1).to_string().len());
+        // wide cells and hex cells get a space between them,
+        // hex rows sit half a cell over on odd rows
+        let separator = if width > 1 || size.lattice() == Lattice::Hex { " " } else { "" };
+        let hex_shift = " ".repeat((width + separator.len()) / 2);
+        let label_width = (size.height().max(1) - 1).to_string().len();
+        let mut result = String::new();
+        for layer in 0..size.depth() {
+            if size.depth() > 1 {
+                result += &format!("layer {}\n", layer);
+            }
+            result += &" ".repeat(label_width + 1);
+            for column in 0..size.width() {
+                result += &format!("{:>width$}", column, width = width);
+                result += separator;
+            }
+            result += "\n";
+            for row in 0..size.height() {
+                result += &format!("{:>width$} ", row, width = label_width);
+                if size.lattice() == Lattice::Hex && row % 2 == 1 {
+                    result += &hex_shift;
+                }
+                for column in 0..size.width() {
+                    let point = Point(row, column, layer);
+                    result += &self.cell(board, &point, probability_lookup.get(&point).cloned(), width);
+                    result += separator;
+                }
+                result += "\n";
+            }
+        }
+        result
+    }
+}
+
+fn risk_level(probability: f32) -> usize {
+    if probability <= 0.0 {
+        0
+    } else if probability >= 1.0 {
+        6
+    } else {
+        // 0.2 wide buckets between the two certain ends
+        1 + (probability / 0.2).min(4.0) as usize
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use super::*;
+    use crate::board::BoardSize;
+    use crate::board::FirstClickPolicy;
+    use crate::board::Topology;
+
+    fn strip_ansi(text: &str) -> String {
+        regex::Regex::new("\x1b\\[[0-9;]*m").unwrap().replace_all(text, "").into_owned()
+    }
+
+    #[test]
+    fn labels_line_up_on_wide_boards() {
+        let mut board = Board::new_from_ints(12, 11, 10).unwrap();
+        board.probe(&Point(5, 5, 0));
+        for theme in [Theme::Ascii, Theme::Unicode, Theme::Color, Theme::ColorBlind].iter() {
+            let text = strip_ansi(&Renderer::new(*theme).render(&board, &[]));
+            let lines: Vec<&str> = text.lines().collect();
+            assert_eq!(lines.len(), 12);
+            // every line is as long as the header and the header ends in 11
+            assert!(lines.iter().all(|line| line.chars().count() == lines[0].chars().count()));
+            assert!(lines[0].trim_end().ends_with("10 11"));
+            assert!(lines[11].starts_with("10 "));
+        }
+    }
+
+    #[test]
+    fn small_boards_keep_the_compact_layout() {
+        let board = Board::new_with_first_click_policy(BoardSize::new(3, 2), 1, 1, FirstClickPolicy::SafeCell).unwrap();
+        assert_eq!(Renderer::default().render(&board, &[]), "  012\n0 □□□\n1 □□□\n");
+        assert_eq!(Renderer::new(Theme::Ascii).render(&board, &[]), "  012\n0 ###\n1 ###\n");
+    }
+
+    #[test]
+    fn ascii_is_ascii() {
+        let size = BoardSize::with_lattice(8, 8, Lattice::Hex, Topology::Rectangle).unwrap();
+        let mut board = Board::new_with_mines_per_cell(size, 10, 3).unwrap();
+        board.probe(&Point(4, 4, 0));
+        for point in board.get_unknown_points().iter().take(3) {
+            board.toggle_flag(point);
+            board.toggle_flag(point);
+        }
+        let probabilities: Vec<(Point, f32)> = board.get_unknown_points().into_iter().map(|p| (p, 0.5)).collect();
+        let renderer = Renderer::new(Theme::Ascii).with_heatmap(true);
+        assert!(renderer.render(&board, &[]).is_ascii());
+        assert!(renderer.render(&board, &probabilities).contains('+'));
+    }
+
+    #[test]
+    fn colors_and_heatmap() {
+        let mut board = Board::new_from_ints(9, 9, 10).unwrap();
+        board.probe(&Point(4, 4, 0));
+        let unknown = board.get_unknown_points()[0];
+        let plain = Renderer::default().render(&board, &[(unknown, 0.5)]);
+        assert!(!plain.contains('\x1b'));
+        assert!(plain.contains(UNICODE_RISK[3]));
+        let colored = Renderer::new(Theme::Color).render(&board, &[]);
+        assert!(colored.contains('\x1b'));
+        assert_eq!(strip_ansi(&colored), Renderer::default().render(&board, &[]));
+        // the heatmap keeps the glyph and only adds a background
+        let heat = Renderer::new(Theme::ColorBlind).with_heatmap(true).render(&board, &[(unknown, 0.5)]);
+        assert_eq!(strip_ansi(&heat), Renderer::default().render(&board, &[]));
+        assert!(heat.contains(&format!("48;5;{}", COLORBLIND_RISK[3])));
+    }
+
+    #[test]
+    fn risk_levels() {
+        assert_eq!(risk_level(0.0), 0);
+        assert_eq!(risk_level(0.1), 1);
+        assert_eq!(risk_level(0.5), 3);
+        assert_eq!(risk_level(0.99), 5);
+        assert_eq!(risk_level(1.0), 6);
+    }
+
+    #[test]
+    fn theme_names() {
+        assert_eq!(Theme::from_name("ASCII"), Some(Theme::Ascii));
+        assert_eq!(Theme::from_name("colour"), Some(Theme::Color));
+        assert_eq!(Theme::from_name("colorblind"), Some(Theme::ColorBlind));
+        assert_eq!(Theme::from_name("sepia"), None);
+    }
+}
@@ -0,0 +1,451 @@
+use rand::thread_rng;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::fmt::Debug;
+use super::constraint::Solver;
+use super::dimacs::Literal;
+
+// Internally a literal is 2*var for the positive and 2*var+1 for the negative form
+type Lit = usize;
+
+fn to_lit(literal: Literal) -> Lit {
+    let var = (literal.unsigned_abs() - 1) as usize;
+    2 * var + (literal < 0) as usize
+}
+
+fn var(lit: Lit) -> usize {
+    lit / 2
+}
+
+fn is_negative(lit: Lit) -> bool {
+    lit & 1 == 1
+}
+
+fn negate(lit: Lit) -> Lit {
+    lit ^ 1
+}
+
+// at most `bound` of `literals` may be true
+struct AtMost {
+    literals: Vec<Lit>,
+    bound: usize
+}
+
+// CDCL solver over clauses and native at-most-k constraints. Cardinality
+// constraints are never expanded into clauses, they explain their
+// propagations lazily with a clause built from the literals that are true.
+pub struct SatSolver {
+    variable_count: usize,
+    clauses: Vec<Vec<Lit>>,
+    cardinalities: Vec<AtMost>,
+    clause_occurrences: Vec<Vec<usize>>,
+    cardinality_occurrences: Vec<Vec<usize>>,
+    trivially_unsat: bool,
+    assignment: Vec<Option<bool>>,
+    level: Vec<usize>,
+    reason: Vec<Option<Vec<Lit>>>,
+    activity: Vec<f64>,
+    trail: Vec<Lit>,
+    trail_limits: Vec<usize>,
+    queue_head: usize
+}
+
+enum Check {
+    Fine,
+    Conflict(Vec<Lit>)
+}
+
+impl SatSolver {
+    pub fn new(variable_count: usize) -> SatSolver {
+        SatSolver{
+            variable_count,
+            clauses: vec![],
+            cardinalities: vec![],
+            clause_occurrences: vec![vec![]; 2 * variable_count],
+            cardinality_occurrences: vec![vec![]; 2 * variable_count],
+            trivially_unsat: false,
+            assignment: vec![None; variable_count],
+            level: vec![0; variable_count],
+            reason: vec![None; variable_count],
+            activity: vec![0.0; variable_count],
+            trail: vec![],
+            trail_limits: vec![],
+            queue_head: 0
+        }
+    }
+
+    pub fn variable_count(&self) -> usize {
+        self.variable_count
+    }
+
+    pub fn add_clause(&mut self, clause: &[Literal]) {
+        let clause: Vec<Lit> = clause.iter().map(|l| to_lit(*l)).collect();
+        self.push_clause(clause);
+    }
+
+    pub fn add_at_most(&mut self, literals: &[Literal], k: i32) {
+        if k < 0 {
+            self.trivially_unsat = true;
+            return
+        }
+        if k as usize >= literals.len() {
+            return
+        }
+        let index = self.cardinalities.len();
+        let literals: Vec<Lit> = literals.iter().map(|l| to_lit(*l)).collect();
+        for lit in &literals {
+            self.cardinality_occurrences[*lit].push(index);
+        }
+        self.cardinalities.push(AtMost{literals, bound: k as usize});
+    }
+
+    pub fn add_at_least(&mut self, literals: &[Literal], k: i32) {
+        let negated: Vec<Literal> = literals.iter().map(|lit| -lit).collect();
+        self.add_at_most(&negated, literals.len() as i32 - k);
+    }
+
+    pub fn add_exactly(&mut self, literals: &[Literal], k: i32) {
+        self.add_at_most(literals, k);
+        self.add_at_least(literals, k);
+    }
+
+    // Finds a model with random decision order and polarity, so repeated
+    // calls sample different solutions. Learned clauses are kept between calls.
+    pub fn solve(&mut self) -> Option<Vec<bool>> {
+        if self.trivially_unsat || self.clauses.iter().any(|clause| clause.is_empty()) {
+            return None
+        }
+        self.backtrack_to(0);
+        self.trail.clear();
+        self.queue_head = 0;
+        self.assignment.iter_mut().for_each(|value| *value = None);
+
+        let mut rng = thread_rng();
+        let mut order: Vec<usize> = (0..self.variable_count).collect();
+        order.shuffle(&mut rng);
+
+        if let Check::Conflict(_) = self.check_everything() {
+            return None
+        }
+        loop {
+            if let Some(conflict) = self.propagate() {
+                if self.trail_limits.is_empty() {
+                    return None
+                }
+                let (learnt, backjump_level) = self.analyze(conflict);
+                self.backtrack_to(backjump_level);
+                let implied = learnt[0];
+                let reason = learnt.clone();
+                self.push_clause(learnt);
+                self.assign(implied, Some(reason));
+                self.activity.iter_mut().for_each(|a| *a *= 0.95);
+                continue
+            }
+            let next = order.iter()
+                .filter(|v| self.assignment[**v].is_none())
+                .fold(None, |best: Option<usize>, v| match best {
+                    Some(b) if self.activity[b] >= self.activity[*v] => Some(b),
+                    _ => Some(*v)
+                });
+            match next {
+                None => {
+                    let model = self.assignment.iter().map(|value| value.unwrap_or(false)).collect();
+                    return Some(model)
+                },
+                Some(v) => {
+                    self.trail_limits.push(self.trail.len());
+                    let lit = 2 * v + rng.gen::<bool>() as usize;
+                    self.assign(lit, None);
+                }
+            }
+        }
+    }
+
+    fn push_clause(&mut self, clause: Vec<Lit>) {
+        let index = self.clauses.len();
+        for lit in &clause {
+            self.clause_occurrences[*lit].push(index);
+        }
+        self.clauses.push(clause);
+    }
+
+    fn value(&self, lit: Lit) -> Option<bool> {
+        self.assignment[var(lit)].map(|value| value != is_negative(lit))
+    }
+
+    fn current_level(&self) -> usize {
+        self.trail_limits.len()
+    }
+
+    fn assign(&mut self, lit: Lit, reason: Option<Vec<Lit>>) {
+        let v = var(lit);
+        self.assignment[v] = Some(!is_negative(lit));
+        self.level[v] = self.current_level();
+        self.reason[v] = reason;
+        self.trail.push(lit);
+    }
+
+    fn backtrack_to(&mut self, level: usize) {
+        if self.current_level() <= level {
+            return
+        }
+        let limit = self.trail_limits[level];
+        for lit in self.trail.drain(limit..) {
+            self.assignment[var(lit)] = None;
+            self.reason[var(lit)] = None;
+        }
+        self.trail_limits.truncate(level);
+        self.queue_head = self.trail.len();
+    }
+
+    fn check_everything(&mut self) -> Check {
+        for index in 0..self.clauses.len() {
+            if let Check::Conflict(c) = self.check_clause(index) {
+                return Check::Conflict(c)
+            }
+        }
+        for index in 0..self.cardinalities.len() {
+            if let Check::Conflict(c) = self.check_cardinality(index) {
+                return Check::Conflict(c)
+            }
+        }
+        Check::Fine
+    }
+
+    fn propagate(&mut self) -> Option<Vec<Lit>> {
+        while self.queue_head < self.trail.len() {
+            let lit = self.trail[self.queue_head];
+            self.queue_head += 1;
+            let false_lit = negate(lit);
+            for i in 0..self.clause_occurrences[false_lit].len() {
+                let index = self.clause_occurrences[false_lit][i];
+                if let Check::Conflict(c) = self.check_clause(index) {
+                    return Some(c)
+                }
+            }
+            for i in 0..self.cardinality_occurrences[lit].len() {
+                let index = self.cardinality_occurrences[lit][i];
+                if let Check::Conflict(c) = self.check_cardinality(index) {
+                    return Some(c)
+                }
+            }
+        }
+        None
+    }
+
+    fn check_clause(&mut self, index: usize) -> Check {
+        let mut unassigned = None;
+        let mut unassigned_count = 0;
+        for lit in &self.clauses[index] {
+            match self.value(*lit) {
+                Some(true) => return Check::Fine,
+                Some(false) => {},
+                None => {
+                    unassigned = Some(*lit);
+                    unassigned_count += 1;
+                }
+            }
+        }
+        match (unassigned_count, unassigned) {
+            (0, _) => Check::Conflict(self.clauses[index].clone()),
+            (1, Some(lit)) => {
+                let mut reason = vec![lit];
+                reason.extend(self.clauses[index].iter().filter(|l| **l != lit));
+                self.assign(lit, Some(reason));
+                Check::Fine
+            },
+            _ => Check::Fine
+        }
+    }
+
+    fn check_cardinality(&mut self, index: usize) -> Check {
+        let constraint = &self.cardinalities[index];
+        let true_lits: Vec<Lit> = constraint.literals.iter()
+            .filter(|lit| self.value(**lit) == Some(true))
+            .copied()
+            .collect();
+        if true_lits.len() > constraint.bound {
+            return Check::Conflict(true_lits.iter().take(constraint.bound + 1).map(|lit| negate(*lit)).collect())
+        }
+        if true_lits.len() == constraint.bound {
+            let unassigned: Vec<Lit> = constraint.literals.iter()
+                .filter(|lit| self.value(**lit).is_none())
+                .copied()
+                .collect();
+            for lit in unassigned {
+                if self.value(lit).is_some() {
+                    continue
+                }
+                let mut reason = vec![negate(lit)];
+                reason.extend(true_lits.iter().map(|l| negate(*l)));
+                self.assign(negate(lit), Some(reason));
+            }
+        }
+        Check::Fine
+    }
+
+    // first unique implication point analysis, returns the learnt clause
+    // with the asserting literal first and the level to jump back to
+    fn analyze(&mut self, conflict: Vec<Lit>) -> (Vec<Lit>, usize) {
+        let mut seen = vec![false; self.variable_count];
+        let mut learnt = vec![0];
+        let mut pending = 0;
+        let mut clause = conflict;
+        let mut index = self.trail.len();
+        let mut resolved: Option<Lit> = None;
+        loop {
+            for lit in &clause {
+                let v = var(*lit);
+                if resolved.map(var) == Some(v) || seen[v] || self.level[v] == 0 {
+                    continue
+                }
+                seen[v] = true;
+                self.activity[v] += 1.0;
+                if self.level[v] == self.current_level() {
+                    pending += 1;
+                } else {
+                    learnt.push(*lit);
+                }
+            }
+            loop {
+                index -= 1;
+                if seen[var(self.trail[index])] {
+                    break
+                }
+            }
+            let lit = self.trail[index];
+            seen[var(lit)] = false;
+            pending -= 1;
+            resolved = Some(lit);
+            if pending == 0 {
+                break
+            }
+            clause = self.reason[var(lit)].clone().expect("implied literal without a reason");
+        }
+        learnt[0] = negate(resolved.expect("conflict analysis resolved nothing"));
+
+        let mut backjump_level = 0;
+        for i in 1..learnt.len() {
+            let level = self.level[var(learnt[i])];
+            if level > backjump_level {
+                backjump_level = level;
+                learnt.swap(1, i);
+            }
+        }
+        (learnt, backjump_level)
+    }
+}
+
+// Boolean variables identified by arbitrary ids on top of SatSolver
+pub struct CardinalitySolver<S: Hash + Eq + Copy + Debug> {
+    sat: SatSolver,
+    ids: Vec<S>,
+    literals: HashMap<S, Literal>
+}
+
+impl<S: Hash + Eq + Copy + Debug> CardinalitySolver<S> {
+    pub fn new(ids: Vec<S>) -> CardinalitySolver<S> {
+        let sat = SatSolver::new(ids.len());
+        let literals = ids.iter().enumerate()
+            .map(|(i, id)| (*id, i as Literal + 1))
+            .collect();
+        CardinalitySolver{sat, ids, literals}
+    }
+
+    fn literals_for(&self, ids: &[S], value: bool) -> Vec<Literal> {
+        ids.iter()
+            .map(|id| *self.literals.get(id).expect("unknown variable id"))
+            .map(|lit| if value { lit } else { -lit })
+            .collect()
+    }
+
+    // satisfied when at least one of the variables takes its paired value
+    pub fn add_clause(&mut self, clause: &[(S, bool)]) {
+        let literals: Vec<Literal> = clause.iter()
+            .flat_map(|(id, value)| self.literals_for(&[*id], *value))
+            .collect();
+        self.sat.add_clause(&literals);
+    }
+
+    // at most k of the variables take `value`
+    pub fn add_at_most(&mut self, ids: &[S], value: bool, k: i32) {
+        let literals = self.literals_for(ids, value);
+        self.sat.add_at_most(&literals, k);
+    }
+
+    pub fn add_exactly(&mut self, ids: &[S], value: bool, k: i32) {
+        let literals = self.literals_for(ids, value);
+        self.sat.add_exactly(&literals, k);
+    }
+}
+
+impl<S: Hash + Eq + Copy + Debug> Solver<S, bool> for CardinalitySolver<S> {
+    fn solve(&mut self) -> Option<HashMap<S, bool>> {
+        let model = self.sat.solve()?;
+        Some(self.ids.iter().copied().zip(model).collect())
+    }
+}
+
+#[cfg(test)]
+mod sat_tests {
+    use super::*;
+    use super::super::dimacs::Cnf;
+    use proptest::prelude::*;
+
+    fn brute_force_satisfiable(variable_count: usize, clauses: &[Vec<Literal>], bounds: &[(Vec<Literal>, i32)]) -> bool {
+        (0..1usize << variable_count).any(|bits| {
+            let value = |lit: &Literal| (bits & (1 << (lit.abs() - 1)) != 0) == (*lit > 0);
+            clauses.iter().all(|clause| clause.iter().any(value))
+                && bounds.iter().all(|(lits, k)| lits.iter().filter(|l| value(l)).count() as i32 <= *k)
+        })
+    }
+
+    fn to_literals(raw: &[(Literal, bool)]) -> Vec<Literal> {
+        raw.iter().map(|(v, neg)| if *neg { -v } else { *v }).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn agrees_with_brute_force(
+            ref raw_clauses in prop::collection::vec(prop::collection::vec((1..9, any::<bool>()), 1..4), 0..20),
+            ref raw_bounds in prop::collection::vec((prop::collection::vec((1..9, any::<bool>()), 1..6), 0..4i32), 0..4)) {
+            let clauses: Vec<Vec<Literal>> = raw_clauses.iter().map(|raw| to_literals(raw)).collect();
+            let bounds: Vec<(Vec<Literal>, i32)> = raw_bounds.iter().map(|(raw, k)| (to_literals(raw), *k)).collect();
+            let mut solver = SatSolver::new(8);
+            clauses.iter().for_each(|clause| solver.add_clause(clause));
+            bounds.iter().for_each(|(lits, k)| solver.add_at_most(lits, *k));
+            let expected = brute_force_satisfiable(8, &clauses, &bounds);
+            match solver.solve() {
+                None => prop_assert!(!expected),
+                Some(model) => {
+                    let lits: Vec<Literal> = model.iter().enumerate()
+                        .map(|(i, v)| if *v { i as Literal + 1 } else { -(i as Literal + 1) })
+                        .collect();
+                    let mut cnf = Cnf::new();
+                    cnf.variable_count = 8;
+                    clauses.iter().for_each(|clause| cnf.add_clause(clause.clone()));
+                    prop_assert!(cnf.is_satisfied_by(&lits));
+                    for (bound_lits, k) in &bounds {
+                        let count = bound_lits.iter().filter(|l| lits.contains(l)).count() as i32;
+                        prop_assert!(count <= *k);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exactly_is_respected_across_calls() {
+        let mut solver = CardinalitySolver::new((0..10).collect());
+        let ids: Vec<usize> = (0..10).collect();
+        solver.add_exactly(&ids, true, 3);
+        solver.add_at_most(&ids[..5], true, 1);
+        for _ in 0..20 {
+            let model = solver.solve().expect("should be satisfiable");
+            assert_eq!(model.values().filter(|v| **v).count(), 3);
+            assert!(ids[..5].iter().filter(|id| model[id]).count() <= 1);
+        }
+    }
+}
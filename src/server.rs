@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use super::ai::NaiveAI;
+use super::board::Board;
+use super::board::BoardSize;
+use super::board::FirstClickPolicy;
+use super::game::Game;
+use super::json::Json;
+use super::protocol;
+use super::Command;
+
+// Many games at once over HTTP, everything in and out is JSON
+//   POST   /games                     {"width", "height", "mines", "seed" (optional)}, makes a game
+//   GET    /games/ID                  what the player sees, the same cells as the bot protocol
+//   POST   /games/ID/moves            {"action":"click"|"flag"|"chord"|"complete", "row", "column", "layer" (optional)}
+//   GET    /games/ID/probabilities    the AI's chance of a mine for each cell next to a number
+//   DELETE /games/ID                  throws the game away
+// Games answer with their "id" and "status" ("playing", "won" or "lost") on top of the
+// player's view, and once it's over "mine_cells" says where the mines were. Problems come
+// back as {"error"} with a 4xx status.
+pub struct GameServer {
+    games: Mutex<Games>,
+    // milliseconds the AI gets for probabilities
+    think_time: u64
+}
+
+#[derive(Default)]
+struct Games {
+    next_id: u64,
+    boards: HashMap<u64, Arc<Mutex<Game>>>
+}
+
+// keeps one client from eating all the memory
+const MAX_GAMES: usize = 10000;
+const MAX_AREA: usize = 100000;
+const MAX_BODY: u64 = 65536;
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub(crate) body: Json
+}
+
+impl Response {
+    // the body as it goes over the wire
+    pub fn text(&self) -> String {
+        self.body.to_string()
+    }
+
+    fn ok(body: Json) -> Response {
+        Response{status: 200, body}
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response{status, body: Json::object(vec![("error", Json::string(message))])}
+    }
+}
+
+// a running server, it stops when this is dropped
+pub struct Listener {
+    http: Arc<tiny_http::Server>,
+    workers: Vec<thread::JoinHandle<()>>
+}
+
+impl Default for GameServer {
+    fn default() -> GameServer {
+        GameServer::new()
+    }
+}
+
+impl GameServer {
+    pub fn new() -> GameServer {
+        GameServer{games: Mutex::new(Games::default()), think_time: 1000}
+    }
+
+    pub fn with_think_time(mut self, milliseconds: u64) -> GameServer {
+        self.think_time = milliseconds;
+        self
+    }
+
+    // answers requests on address ("127.0.0.1:8080", port 0 picks one) with a thread per
+    // worker, so slow probabilities for one game don't hold up the others
+    pub fn listen(self, address: &str, workers: usize) -> io::Result<Listener> {
+        let http = tiny_http::Server::http(address).map_err(|error| io::Error::other(error.to_string()))?;
+        let http = Arc::new(http);
+        let games = Arc::new(self);
+        let workers = (0..workers.max(1)).map(|_| {
+            let http = Arc::clone(&http);
+            let games = Arc::clone(&games);
+            thread::spawn(move || {
+                // recv fails once the listener is dropped
+                while let Ok(request) = http.recv() {
+                    games.respond(request);
+                }
+            })
+        }).collect();
+        Ok(Listener{http, workers})
+    }
+
+    fn respond(&self, mut request: tiny_http::Request) {
+        let mut body = String::new();
+        let response = match request.as_reader().take(MAX_BODY).read_to_string(&mut body) {
+            // browsers ask before sending JSON to another origin
+            Ok(_) if request.method() == &tiny_http::Method::Options => Response::ok(Json::Null),
+            Ok(_) => self.handle(request.method().as_str(), request.url(), &body),
+            Err(_) => Response::error(400, "the body has to be UTF-8")
+        };
+        let header = |name: &str, value: &str| tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes())
+            .expect("a valid header");
+        let reply = tiny_http::Response::from_string(response.text())
+            .with_status_code(response.status)
+            .with_header(header("Content-Type", "application/json"))
+            .with_header(header("Access-Control-Allow-Origin", "*"))
+            .with_header(header("Access-Control-Allow-Methods", "GET, POST, DELETE"))
+            .with_header(header("Access-Control-Allow-Headers", "Content-Type"));
+        // the client went away, nothing to tell it
+        let _ = request.respond(reply);
+    }
+
+    // everything but the HTTP, url is the path with an optional query string
+    pub fn handle(&self, method: &str, url: &str, body: &str) -> Response {
+        let path = url.split('?').next().unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        let parse = || Json::parse(body).map_err(|problem| Response::error(400, &format!("not JSON: {}", problem)));
+        let result = match (method, segments.as_slice()) {
+            ("POST", ["games"]) => parse().and_then(|request| self.create(&request)),
+            (_, ["games"]) => Err(Response::error(405, "POST to make a game")),
+            (method, ["games", id, rest @ ..]) => {
+                let id: u64 = match id.parse() {
+                    Ok(id) => id,
+                    Err(_) => return Response::error(404, "no such game")
+                };
+                match (method, rest) {
+                    ("GET", []) => self.view(id),
+                    ("DELETE", []) => self.delete(id),
+                    ("POST", ["moves"]) => parse().and_then(|request| self.play(id, &request)),
+                    ("GET", ["probabilities"]) => self.probabilities(id),
+                    (_, []) | (_, ["moves"]) | (_, ["probabilities"]) => Err(Response::error(405, "wrong method")),
+                    _ => Err(Response::error(404, "not found"))
+                }
+            },
+            _ => Err(Response::error(404, "not found"))
+        };
+        result.unwrap_or_else(|error| error)
+    }
+
+    fn game(&self, id: u64) -> Result<Arc<Mutex<Game>>, Response> {
+        let games = self.games.lock().expect("a request panicked");
+        games.boards.get(&id).cloned().ok_or_else(|| Response::error(404, "no such game"))
+    }
+
+    fn create(&self, request: &Json) -> Result<Response, Response> {
+        let number = |name: &str| request.get(name)
+            .map(|value| value.as_usize().ok_or_else(|| Response::error(400, &format!("{} has to be a whole number", name))))
+            .transpose();
+        let width = number("width")?.unwrap_or(9);
+        let height = number("height")?.unwrap_or(9);
+        let mines = number("mines")?.unwrap_or(10);
+        if width.checked_mul(height).is_none_or(|area| area > MAX_AREA) {
+            return Err(Response::error(400, &format!("boards go up to {} cells", MAX_AREA)))
+        }
+        let first_click = match request.get("first_click") {
+            None => FirstClickPolicy::default(),
+            Some(name) => name.as_str().and_then(FirstClickPolicy::from_name).ok_or_else(|| Response::error(400,
+                "first_click is unprotected, safe-cell, safe-opening, safe-radius N or windows-classic"))?
+        };
+        // a wider radius keeps nothing more clear, it only makes the checks slower
+        if let FirstClickPolicy::SafeRadius(radius) = first_click {
+            if radius > width.max(height) {
+                return Err(Response::error(400, &format!("safe-radius goes up to {} on this board", width.max(height))))
+            }
+        }
+        let board = Board::new_with_first_click_policy(BoardSize::new(width, height), mines, 1, first_click)
+            .map_err(|error| Response::error(400, &error.to_string()))?;
+        let board = match number("seed")? {
+            Some(seed) => board.with_seed(seed as u64),
+            None => board
+        };
+
+        let mut games = self.games.lock().expect("a request panicked");
+        if games.boards.len() >= MAX_GAMES {
+            return Err(Response::error(503, "too many games, delete some"))
+        }
+        let id = games.next_id;
+        games.next_id += 1;
+        let game = Game::new(board);
+        let body = with_id(id, game.view());
+        games.boards.insert(id, Arc::new(Mutex::new(game)));
+        Ok(Response{status: 201, body})
+    }
+
+    fn view(&self, id: u64) -> Result<Response, Response> {
+        let game = self.game(id)?;
+        let game = game.lock().expect("a request panicked");
+        Ok(Response::ok(with_id(id, game.view())))
+    }
+
+    fn delete(&self, id: u64) -> Result<Response, Response> {
+        let mut games = self.games.lock().expect("a request panicked");
+        match games.boards.remove(&id) {
+            Some(_) => Ok(Response::ok(Json::object(vec![("deleted", Json::Number(id as f64))]))),
+            None => Err(Response::error(404, "no such game"))
+        }
+    }
+
+    fn play(&self, id: u64, request: &Json) -> Result<Response, Response> {
+        let action = match protocol::command_from_json(request) {
+            Ok(Command::Move(action)) => action,
+            Ok(_) => return Err(Response::error(400, "only moves here")),
+            Err(problem) => return Err(Response::error(400, &problem))
+        };
+        let game = self.game(id)?;
+        let mut game = game.lock().expect("a request panicked");
+        if game.is_over() {
+            return Err(Response::error(409, "the game is over"))
+        }
+        game.play(action).map_err(|problem| Response::error(400, &problem))?;
+        Ok(Response::ok(with_id(id, game.view())))
+    }
+
+    fn probabilities(&self, id: u64) -> Result<Response, Response> {
+        // sampling takes a while, other requests for the game shouldn't wait on it
+        let board = self.game(id)?.lock().expect("a request panicked").board().clone();
+        let probabilities = NaiveAI::new(0, self.think_time).with_verbose(false).mine_probabilities(&board);
+        Ok(Response::ok(Json::object(vec![
+            ("id", Json::Number(id as f64)),
+            ("cells", protocol::probabilities_json(&probabilities))
+        ])))
+    }
+}
+
+fn with_id(id: u64, view: Json) -> Json {
+    match view {
+        Json::Object(mut fields) => {
+            fields.insert(0, (String::from("id"), Json::Number(id as f64)));
+            Json::Object(fields)
+        },
+        view => view
+    }
+}
+
+impl Listener {
+    pub fn address(&self) -> SocketAddr {
+        self.http.server_addr().to_ip().expect("listening on TCP")
+    }
+
+    // blocks for as long as the server runs
+    pub fn wait(mut self) {
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        for _ in &self.workers {
+            self.http.unblock();
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod server_tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use crate::board::Content;
+    use crate::board::Point;
+
+    fn field<'a>(response: &'a Response, name: &str) -> &'a Json {
+        response.body.get(name).unwrap_or_else(|| panic!("no {} in {}", name, response.body))
+    }
+
+    #[test]
+    fn a_game_from_start_to_finish() {
+        let server = GameServer::new().with_think_time(50);
+        // too tight for nothing within 2 of the first click, fine if only the cell itself is safe
+        let refused = server.handle("POST", "/games", r#"{"width":3,"height":1,"mines":1}"#);
+        assert_eq!(refused.status, 400);
+        assert!(field(&refused, "error").as_str().unwrap().contains("first click"));
+        let refused = server.handle("POST", "/games", r#"{"width":300,"height":300,"mines":89999,"first_click":"safe-radius 1000000"}"#);
+        assert_eq!(refused.status, 400);
+        assert_eq!(field(&refused, "error").as_str(), Some("safe-radius goes up to 300 on this board"));
+        let created = server.handle("POST", "/games", r#"{"width":3,"height":1,"mines":1,"seed":5,"first_click":"safe-cell"}"#);
+        assert_eq!(created.status, 201);
+        assert_eq!(field(&created, "status").as_str(), Some("playing"));
+        let id = field(&created, "id").as_usize().unwrap();
+
+        // the first click is kept safe, then one of the ends holds the mine
+        let played = server.handle("POST", &format!("/games/{}/moves", id), r#"{"action":"click","row":0,"column":1}"#);
+        assert_eq!(played.status, 200, "{}", played.body);
+        assert_eq!(field(&played, "cells").to_string(), r#"[[["?",1,"?"]]]"#);
+        let game = server.game(id as u64).unwrap();
+        let mine = if matches!(game.lock().unwrap().board().retrieve_cell(&Point(0, 0, 0)).content, Content::Mine(_)) { 0 } else { 2 };
+        let safe = 2 - mine;
+        let played = server.handle("POST", &format!("/games/{}/moves", id), &format!(r#"{{"action":"click","row":0,"column":{}}}"#, safe));
+        assert_eq!(field(&played, "status").as_str(), Some("playing"));
+        let probabilities = server.handle("GET", &format!("/games/{}/probabilities", id), "");
+        assert_eq!(field(&probabilities, "cells").to_string(), format!(r#"[{{"row":0,"column":{},"layer":0,"probability":1}}]"#, mine));
+
+        let played = server.handle("POST", &format!("/games/{}/moves", id), &format!(r#"{{"action":"flag","row":0,"column":{}}}"#, mine));
+        assert_eq!(field(&played, "status").as_str(), Some("won"));
+        assert_eq!(field(&played, "mine_cells").to_string(), format!("[[0,{},0]]", mine));
+        let late = server.handle("POST", &format!("/games/{}/moves", id), r#"{"action":"click","row":0,"column":0}"#);
+        assert_eq!(late.status, 409);
+    }
+
+    #[test]
+    fn seeds_and_separate_games() {
+        let server = GameServer::new();
+        let deal = |seed: u32| {
+            let created = server.handle("POST", "/games", &format!(r#"{{"width":8,"height":8,"mines":10,"seed":{}}}"#, seed));
+            let id = field(&created, "id").as_usize().unwrap();
+            let played = server.handle("POST", &format!("/games/{}/moves", id), r#"{"action":"click","row":4,"column":4}"#);
+            (id, field(&played, "cells").to_string())
+        };
+        let (first, cells) = deal(3);
+        let (second, again) = deal(3);
+        assert_ne!(first, second);
+        assert_eq!(cells, again);
+
+        assert_eq!(server.handle("DELETE", &format!("/games/{}", first), "").status, 200);
+        assert_eq!(server.handle("GET", &format!("/games/{}", first), "").status, 404);
+        assert_eq!(server.handle("GET", &format!("/games/{}?fresh", second), "").status, 200);
+    }
+
+    #[test]
+    fn bad_requests() {
+        let server = GameServer::new();
+        assert_eq!(server.handle("POST", "/games", "{").status, 400);
+        assert_eq!(server.handle("POST", "/games", r#"{"width":-3}"#).status, 400);
+        assert_eq!(server.handle("POST", "/games", r#"{"width":100000,"height":100000}"#).status, 400);
+        assert_eq!(server.handle("POST", "/games", r#"{"width":2,"height":2,"mines":9}"#).status, 400);
+        assert_eq!(server.handle("GET", "/games", "").status, 405);
+        assert_eq!(server.handle("GET", "/elsewhere", "").status, 404);
+        assert_eq!(server.handle("GET", "/games/nope", "").status, 404);
+
+        server.handle("POST", "/games", "{}");
+        assert_eq!(server.handle("POST", "/games/0/moves", r#"{"action":"click","row":20,"column":0}"#).status, 400);
+        assert_eq!(server.handle("POST", "/games/0/moves", r#"{"action":"undo"}"#).status, 400);
+        assert_eq!(server.handle("POST", "/games/1/moves", r#"{"action":"click","row":0,"column":0}"#).status, 404);
+    }
+
+    #[test]
+    fn over_localhost() {
+        let listener = GameServer::new().listen("127.0.0.1:0", 2).unwrap();
+        let body = r#"{"width":5,"height":5,"mines":3}"#;
+        let mut stream = TcpStream::connect(listener.address()).unwrap();
+        write!(stream, "POST /games HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 201"), "{}", reply);
+        assert!(reply.contains("application/json"));
+        assert!(reply.contains("Access-Control-Allow-Origin: *"));
+        let json = Json::parse(reply.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(json.get("width").and_then(Json::as_usize), Some(5));
+    }
+}
@@ -0,0 +1,209 @@
+use std::time::Duration;
+use super::board::Board;
+use super::board::BoardError;
+use super::interaction;
+use super::ActionType;
+
+const HEADER: &str = "minesweeper game";
+const BOARD_HEADER: &str = "minesweeper board";
+
+// A game in progress as game_loop plays it: the board, the moves that led to it
+// with the board before each one (for undo) and how long it has been played.
+// No random number generator state is saved because a board only draws random
+// numbers when it deals, for the mines and any noisy numbers, and the save holds
+// what the deal drew. A game saved before its deal has nothing random yet but
+// its seed, if it was given one, and that saves with the board. An unseeded
+// game (every one from the command line) deals afresh, as if it was never saved
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub board: Board,
+    history: Vec<(Board, ActionType)>,
+    elapsed: Duration
+}
+
+impl Session {
+    pub fn new(board: Board) -> Session {
+        Session{board, history: vec![], elapsed: Duration::from_secs(0)}
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn set_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed = elapsed;
+    }
+
+    // oldest first
+    pub fn moves(&self) -> Vec<ActionType> {
+        self.history.iter().map(|(_, action)| *action).collect()
+    }
+
+    // plays the move and returns how many mines it set off
+    pub(crate) fn play(&mut self, action: ActionType) -> usize {
+        self.history.push((self.board.clone(), action));
+        super::make_move(&mut self.board, action)
+    }
+
+    // false if there's nothing to take back
+    pub(crate) fn undo(&mut self) -> bool {
+        match self.history.pop() {
+            Some((board, _)) => {
+                self.board = board;
+                true
+            },
+            None => false
+        }
+    }
+
+    // The game, then the moves as HumanAgent reads them and the board they start
+    // from and the one they lead to, as Board::to_save_string writes them:
+    //   minesweeper game
+    //   elapsed 754.250
+    //   move click 3 4
+    //   minesweeper board
+    //   ...
+    // A first move that dealt the mines is saved after the deal, so it plays
+    // back the same way. Loading takes the deal back out of the boards before
+    // it, undoing that far gives an undealt board whose next click deals again
+    pub fn to_save_string(&self) -> String {
+        let mut text = format!("{}\nelapsed {:.3}\n", HEADER, self.elapsed.as_secs_f64());
+        for (_, action) in &self.history {
+            text += &format!("move {}\n", action);
+        }
+        let start = match self.history.first() {
+            Some((start, _)) if !start.initialized => start.dealt_like(&self.board),
+            Some((start, _)) => start.clone(),
+            None => self.board.clone()
+        };
+        text += &start.to_save_string();
+        text += &self.board.to_save_string();
+        text
+    }
+
+    // Plays the moves again from the first board to rebuild the history, they have
+    // to end on the second one. A saved board on its own starts a new session
+    pub fn from_save_string(text: &str) -> Result<Session, BoardError> {
+        let invalid = |problem: String| BoardError::InvalidSave(problem);
+        if text.starts_with(BOARD_HEADER) {
+            return Board::from_save_string(text).map(Session::new)
+        }
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(invalid(String::from("missing the header")))
+        }
+        let mut elapsed = None;
+        let mut moves = vec![];
+        let mut boards: Vec<String> = vec![];
+        for line in lines {
+            if line == BOARD_HEADER {
+                boards.push(String::new());
+            }
+            if let Some(board) = boards.last_mut() {
+                *board += line;
+                board.push('\n');
+                continue
+            }
+            match line.split_once(' ') {
+                Some(("elapsed", seconds)) => elapsed = Some(seconds.parse::<f64>().ok()
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| invalid(format!("bad elapsed time {}", seconds)))?),
+                Some(("move", action)) => moves.push(interaction::parse_action(action).map_err(invalid)?),
+                _ if line.trim().is_empty() => {},
+                _ => return Err(invalid(format!("don't know \"{}\"", line)))
+            }
+        }
+        let elapsed = elapsed.ok_or_else(|| invalid(String::from("no elapsed time")))?;
+        let (start, end) = match boards.as_slice() {
+            [start, end] => (Board::from_save_string(start)?, Board::from_save_string(end)?),
+            _ => return Err(invalid(format!("{} boards, there should be two", boards.len())))
+        };
+
+        let mut session = Session::new(start);
+        session.elapsed = elapsed;
+        for action in moves {
+            if session.board.is_won() || !session.board.size.is_playable(&action.point()) {
+                return Err(invalid(format!("can't {} there", action)))
+            }
+            if session.play(action) > 0 {
+                return Err(invalid(format!("{} sets off a mine", action)))
+            }
+        }
+        if session.board.to_save_string() != end.to_save_string() {
+            return Err(invalid(String::from("the moves don't lead to the saved board")))
+        }
+        // boards given their mines have nothing to take back
+        if session.history.first().is_some_and(|(start, _)| start.dealt_with().is_some()) {
+            let dealing = session.history.iter().position(|(_, action)| matches!(action, ActionType::Click(_)));
+            for (board, _) in session.history.iter_mut().take(dealing.map_or(0, |dealing| dealing + 1)) {
+                *board = board.undealt();
+            }
+        }
+        Ok(session)
+    }
+}
+
+// minutes and seconds, 12:05
+pub fn clock_time(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod session_tests {
+    use super::*;
+    use crate::board::BoardSize;
+    use crate::board::Point;
+
+    fn played() -> Session {
+        let mut session = Session::new(Board::new_from_ints(9, 9, 10).unwrap().with_seed(5));
+        session.play(ActionType::Flag(Point(8, 8, 0)));
+        session.play(ActionType::Click(Point(4, 4, 0)));
+        let unknown = session.board.get_unknown_points()[0];
+        session.play(ActionType::Flag(unknown));
+        session.set_elapsed(Duration::from_millis(754_250));
+        session
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let session = played();
+        let saved = session.to_save_string();
+        assert!(saved.starts_with("minesweeper game\nelapsed 754.250\nmove flag 8 8\nmove click 4 4\n"), "{}", saved);
+        let mut loaded = Session::from_save_string(&saved).unwrap();
+        assert_eq!(loaded.to_save_string(), saved);
+        assert_eq!(loaded.elapsed(), Duration::from_millis(754_250));
+        assert_eq!(loaded.moves(), session.moves());
+        assert_eq!(loaded.board.seed(), Some(5));
+
+        // undo goes back through the moves, past the deal to the flag on a board with no mines yet
+        assert!(loaded.undo() && loaded.undo());
+        assert!(!loaded.board.initialized);
+        assert_eq!(loaded.board.dealt_with(), None);
+        assert!(loaded.board.retrieve_cell(&Point(8, 8, 0)).knowledge.is_flag());
+        // so the next first click is protected again, and the seed deals it the same way
+        let mut replayed = loaded.clone();
+        replayed.play(ActionType::Click(Point(4, 4, 0)));
+        assert_eq!(replayed.board.to_save_string(), session.history[2].0.to_save_string());
+        assert!(loaded.undo() && !loaded.undo());
+        assert_eq!(loaded.board.get_unknown_points().len(), 81);
+        assert_eq!(clock_time(session.elapsed()), "12:34");
+    }
+
+    #[test]
+    fn bare_boards_and_bad_saves() {
+        let board = Board::new_with_mines(BoardSize::new(3, 3), &[Point(0, 0, 0)]).unwrap();
+        let session = Session::from_save_string(&board.to_save_string()).unwrap();
+        assert!(session.moves().is_empty());
+        assert_eq!(session.elapsed(), Duration::from_secs(0));
+
+        let saved = played().to_save_string();
+        let edited = |from: &str, to: &str| Session::from_save_string(&saved.replacen(from, to, 1));
+        assert!(edited("elapsed 754.250", "elapsed -1").is_err());
+        assert!(edited("move click 4 4", "move click 40 4").is_err());
+        assert!(edited("move click 4 4", "move explode 4 4").is_err());
+        assert!(edited("move click 4 4\n", "").is_err());
+        assert!(edited("minesweeper game", "minesweeper").is_err());
+        assert!(Session::from_save_string(&saved.replace("minesweeper board", "")).is_err());
+    }
+}
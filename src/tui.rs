@@ -0,0 +1,367 @@
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
+use crossterm::cursor;
+use crossterm::event;
+use crossterm::event::Event;
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use crossterm::event::KeyModifiers;
+use crossterm::event::MouseButton;
+use crossterm::event::MouseEvent;
+use crossterm::event::MouseEventKind;
+use crossterm::queue;
+use crossterm::style;
+use crossterm::style::Attribute;
+use crossterm::terminal;
+use super::board::Board;
+use super::board::Lattice;
+use super::board::Point;
+use super::render::Renderer;
+use super::render::Theme;
+use super::Agent;
+use super::ActionType;
+use super::Command;
+use std::collections::HashMap;
+
+// Screen layout: the status line, a blank line, then one board row per line.
+// Cells are as wide as the renderer's widest glyph plus a space, hex boards
+// shift odd rows half a cell right.
+const BOARD_ROW: u16 = 2;
+const HELP: &str = "arrows/hjkl move  space click  f flag  c chord  a complete  [ ] layer
+u undo  ? hint  p probabilities  n new game  q quit";
+
+// A full-screen player. It takes over the terminal when created and gives it
+// back when dropped, moves are made with the keyboard or the mouse.
+pub struct TerminalAgent {
+    renderer: Renderer,
+    cursor: Point,
+    // the game loop's clock and when it was told, it ticks on from there
+    clock: (Instant, Duration),
+    // what is on screen, so only changed cells get redrawn
+    drawn_layer: Option<usize>,
+    glyph_width: usize,
+    drawn_changes: usize,
+    drawn_cursor: Point,
+    // shown on the status line and over the board until the next key
+    message: String,
+    probabilities: HashMap<Point, f32>,
+    out: io::Stdout
+}
+
+enum Input {
+    Move(i64, i64),
+    Layer(i64),
+    Command(Command),
+    Redraw,
+    Nothing
+}
+
+impl TerminalAgent {
+    pub fn new() -> io::Result<TerminalAgent> {
+        let mut out = io::stdout();
+        terminal::enable_raw_mode()?;
+        queue!(out, terminal::EnterAlternateScreen, event::EnableMouseCapture, cursor::Hide)?;
+        out.flush()?;
+        Ok(TerminalAgent{
+            renderer: Renderer::new(Theme::Color).with_heatmap(true),
+            cursor: Point(0, 0, 0),
+            clock: (Instant::now(), Duration::from_secs(0)),
+            drawn_layer: None,
+            glyph_width: 1,
+            drawn_changes: 0,
+            drawn_cursor: Point(0, 0, 0),
+            message: String::new(),
+            probabilities: HashMap::new(),
+            out
+        })
+    }
+
+    pub fn with_renderer(mut self, renderer: Renderer) -> TerminalAgent {
+        self.renderer = renderer;
+        self.drawn_layer = None;
+        self
+    }
+
+    fn restore(&mut self) -> io::Result<()> {
+        queue!(self.out, cursor::Show, event::DisableMouseCapture, terminal::LeaveAlternateScreen)?;
+        self.out.flush()?;
+        terminal::disable_raw_mode()
+    }
+
+    fn elapsed(&self) -> u64 {
+        let (told, elapsed) = self.clock;
+        (elapsed + told.elapsed()).as_secs()
+    }
+
+    fn draw(&mut self, board: &Board) -> io::Result<()> {
+        // a different board can be smaller
+        self.move_cursor(board, 0, 0, 0);
+        let layer = self.cursor.2;
+        if self.drawn_layer != Some(layer) {
+            queue!(self.out, terminal::Clear(terminal::ClearType::All))?;
+            self.glyph_width = self.renderer.glyph_width(board);
+            for point in board.size.points().iter().filter(|point| point.2 == layer) {
+                self.draw_cell(board, point)?;
+            }
+            let help_row = BOARD_ROW + board.size.height() as u16 + 1;
+            for (i, line) in HELP.lines().enumerate() {
+                queue!(self.out, cursor::MoveTo(0, help_row + i as u16), style::Print(line))?;
+            }
+            self.drawn_layer = Some(layer);
+        } else {
+            let mut changed: Vec<Point> = board.knowledge_changes_since(self.drawn_changes).iter()
+                .filter(|point| point.2 == layer)
+                .cloned()
+                .collect();
+            if self.drawn_cursor != self.cursor {
+                changed.push(self.drawn_cursor);
+            }
+            for point in &changed {
+                self.draw_cell(board, point)?;
+            }
+        }
+        let cursor = self.cursor;
+        self.draw_cell(board, &cursor)?;
+        self.drawn_changes = board.knowledge_change_count();
+        self.drawn_cursor = self.cursor;
+        self.draw_status(board)?;
+        self.out.flush()
+    }
+
+    fn draw_status(&mut self, board: &Board) -> io::Result<()> {
+        let mut status = format!("Mines: {}  Time: {}", board.remaining_mines(), self.elapsed());
+        if board.size.depth() > 1 {
+            status += &format!("  Layer: {}/{}", self.cursor.2, board.size.depth() - 1);
+        }
+        if !self.message.is_empty() {
+            status += "  ";
+            status += &self.message;
+        }
+        queue!(self.out, cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::CurrentLine), style::Print(status))
+    }
+
+    fn draw_cell(&mut self, board: &Board, point: &Point) -> io::Result<()> {
+        let glyph = self.renderer.cell(board, point, self.probabilities.get(point).cloned(), self.glyph_width);
+        let (column, row) = screen_position(board.lattice(), self.glyph_width, point);
+        queue!(self.out, cursor::MoveTo(column, row))?;
+        if *point == self.cursor {
+            queue!(self.out, style::SetAttribute(Attribute::Reverse), style::Print(glyph), style::SetAttribute(Attribute::Reset))
+        } else {
+            queue!(self.out, style::Print(glyph))
+        }
+    }
+
+    fn read_input(&self, board: &Board) -> io::Result<Input> {
+        // wake up every so often so the timer keeps ticking
+        if !event::poll(Duration::from_millis(250))? {
+            return Ok(Input::Nothing)
+        }
+        Ok(match event::read()? {
+            Event::Key(key) => input_from_key(key, self.cursor),
+            Event::Mouse(mouse) => input_from_mouse(board, mouse, self.glyph_width, self.cursor.2),
+            Event::Resize(_, _) => Input::Redraw,
+            _ => Input::Nothing
+        })
+    }
+
+    // the message and probabilities last until the player does something
+    fn clear_overlay(&mut self) {
+        self.message.clear();
+        if !self.probabilities.is_empty() {
+            self.probabilities.clear();
+            self.drawn_layer = None;
+        }
+    }
+
+    fn move_cursor(&mut self, board: &Board, rows: i64, columns: i64, layers: i64) {
+        let clamp = |value: usize, delta: i64, size: usize| (value as i64 + delta).max(0).min(size as i64 - 1) as usize;
+        let Point(row, column, layer) = self.cursor;
+        self.cursor = Point(clamp(row, rows, board.size.height()),
+                            clamp(column, columns, board.size.width()),
+                            clamp(layer, layers, board.size.depth()));
+    }
+}
+
+impl Drop for TerminalAgent {
+    fn drop(&mut self) {
+        let _ = self.restore();
+    }
+}
+
+impl Agent for TerminalAgent {
+    fn generate_move(&mut self, board: &Board) -> ActionType {
+        loop {
+            match self.next_command(board) {
+                Command::Move(action) => return action,
+                _ => self.message = String::from("only moves here")
+            }
+        }
+    }
+
+    fn next_command(&mut self, board: &Board) -> Command {
+        loop {
+            self.draw(board).expect("Failed to draw");
+            let input = self.read_input(board).expect("Failed to read");
+            if !matches!(input, Input::Nothing) {
+                self.clear_overlay();
+            }
+            match input {
+                Input::Move(rows, columns) => self.move_cursor(board, rows, columns, 0),
+                Input::Layer(layers) => self.move_cursor(board, 0, 0, layers),
+                Input::Command(command) => return command,
+                Input::Redraw => self.drawn_layer = None,
+                Input::Nothing => {}
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.drawn_layer = None;
+        self.drawn_changes = 0;
+    }
+
+    fn show_clock(&mut self, elapsed: Duration) {
+        self.clock = (Instant::now(), elapsed);
+    }
+
+    fn show_board(&mut self, board: &Board) {
+        self.draw(board).expect("Failed to draw");
+    }
+
+    fn show_probabilities(&mut self, board: &Board, probabilities: &[(Point, f32)]) {
+        self.probabilities = probabilities.iter().cloned().collect();
+        if probabilities.is_empty() {
+            self.message = String::from("nothing to work out yet");
+        }
+        self.drawn_layer = None;
+        self.draw(board).expect("Failed to draw");
+    }
+
+    fn show_message(&mut self, message: &str) {
+        self.message = String::from(message);
+    }
+
+    fn show_result(&mut self, board: &Board, won: bool) {
+        self.message = String::from(if won { "you win! press any key" } else { "you lose, press any key" });
+        self.draw(board).expect("Failed to draw");
+        loop {
+            match event::read().expect("Failed to read") {
+                Event::Key(key) if key.kind == KeyEventKind::Press => return,
+                Event::Resize(_, _) => {
+                    self.drawn_layer = None;
+                    self.draw(board).expect("Failed to draw");
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+fn screen_position(lattice: Lattice, glyph_width: usize, point: &Point) -> (u16, u16) {
+    let pitch = glyph_width as u16 + 1;
+    let shift = if lattice == Lattice::Hex && point.0 % 2 == 1 { pitch / 2 } else { 0 };
+    (point.1 as u16 * pitch + shift, BOARD_ROW + point.0 as u16)
+}
+
+// the playable cell drawn at this screen position, if any
+fn point_at(board: &Board, glyph_width: usize, column: u16, row: u16, layer: usize) -> Option<Point> {
+    let pitch = glyph_width as u16 + 1;
+    let row = row.checked_sub(BOARD_ROW)? as usize;
+    let shift = if board.lattice() == Lattice::Hex && row % 2 == 1 { pitch / 2 } else { 0 };
+    let column = column.checked_sub(shift)? / pitch;
+    let point = Point(row, column as usize, layer);
+    if board.size.is_playable(&point) { Some(point) } else { None }
+}
+
+fn input_from_key(key: KeyEvent, cursor: Point) -> Input {
+    if key.kind != KeyEventKind::Press {
+        return Input::Nothing
+    }
+    match key.code {
+        KeyCode::Char('c') | KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => Input::Command(Command::Quit),
+        KeyCode::Left | KeyCode::Char('h') => Input::Move(0, -1),
+        KeyCode::Right | KeyCode::Char('l') => Input::Move(0, 1),
+        KeyCode::Up | KeyCode::Char('k') => Input::Move(-1, 0),
+        KeyCode::Down | KeyCode::Char('j') => Input::Move(1, 0),
+        KeyCode::Char('[') | KeyCode::PageUp => Input::Layer(-1),
+        KeyCode::Char(']') | KeyCode::PageDown => Input::Layer(1),
+        KeyCode::Char(' ') | KeyCode::Enter => Input::Command(Command::Move(ActionType::Click(cursor))),
+        KeyCode::Char('f') => Input::Command(Command::Move(ActionType::Flag(cursor))),
+        KeyCode::Char('c') => Input::Command(Command::Move(ActionType::Chord(cursor))),
+        KeyCode::Char('a') => Input::Command(Command::Move(ActionType::Complete(cursor))),
+        KeyCode::Char('u') => Input::Command(Command::Undo),
+        KeyCode::Char('?') => Input::Command(Command::Hint),
+        KeyCode::Char('p') => Input::Command(Command::Probabilities),
+        KeyCode::Char('n') => Input::Command(Command::New),
+        KeyCode::Char('q') | KeyCode::Esc => Input::Command(Command::Quit),
+        _ => Input::Nothing
+    }
+}
+
+fn input_from_mouse(board: &Board, mouse: MouseEvent, glyph_width: usize, layer: usize) -> Input {
+    let button = match mouse.kind {
+        MouseEventKind::Down(button) => button,
+        _ => return Input::Nothing
+    };
+    match point_at(board, glyph_width, mouse.column, mouse.row, layer) {
+        None => Input::Nothing,
+        Some(point) => Input::Command(Command::Move(match button {
+            MouseButton::Left => ActionType::Click(point),
+            MouseButton::Right => ActionType::Flag(point),
+            MouseButton::Middle => ActionType::Chord(point)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tui_tests {
+    use super::*;
+    use crate::board::BoardSize;
+    use crate::board::FirstClickPolicy;
+    use crate::board::Topology;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn mouse_positions_round_trip() {
+        let square = Board::new_from_ints(5, 4, 3).unwrap();
+        let hex = Board::new_from_size(BoardSize::with_lattice(5, 4, Lattice::Hex, Topology::Rectangle).unwrap(), 3).unwrap();
+        for board in [square, hex].iter() {
+            for glyph_width in 1..3 {
+                for point in board.size.points() {
+                    let (column, row) = screen_position(board.lattice(), glyph_width, &point);
+                    assert_eq!(point_at(board, glyph_width, column, row, 0), Some(point));
+                    // the gap after a cell still belongs to it
+                    assert_eq!(point_at(board, glyph_width, column + glyph_width as u16, row, 0), Some(point));
+                }
+                assert_eq!(point_at(board, glyph_width, 0, 0, 0), None);
+                assert_eq!(point_at(board, glyph_width, 0, BOARD_ROW + 4, 0), None);
+            }
+        }
+    }
+
+    #[test]
+    fn mouse_skips_holes() {
+        let size = BoardSize::from_pattern("..\n. ", Topology::Rectangle).unwrap();
+        let board = Board::new_with_first_click_policy(size, 1, 1, FirstClickPolicy::SafeCell).unwrap();
+        let (column, row) = screen_position(Lattice::Square, 1, &Point(1, 1, 0));
+        assert_eq!(point_at(&board, 1, column, row, 0), None);
+    }
+
+    #[test]
+    fn keys_act_on_the_cursor() {
+        let cursor = Point(2, 3, 0);
+        assert!(matches!(input_from_key(key(KeyCode::Char(' ')), cursor), Input::Command(Command::Move(ActionType::Click(Point(2, 3, 0))))));
+        assert!(matches!(input_from_key(key(KeyCode::Char('f')), cursor), Input::Command(Command::Move(ActionType::Flag(Point(2, 3, 0))))));
+        assert!(matches!(input_from_key(key(KeyCode::Char('c')), cursor), Input::Command(Command::Move(ActionType::Chord(Point(2, 3, 0))))));
+        assert!(matches!(input_from_key(key(KeyCode::Char('h')), cursor), Input::Move(0, -1)));
+        assert!(matches!(input_from_key(key(KeyCode::Down), cursor), Input::Move(1, 0)));
+        assert!(matches!(input_from_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), cursor), Input::Command(Command::Quit)));
+        assert!(matches!(input_from_key(key(KeyCode::Char('u')), cursor), Input::Command(Command::Undo)));
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+use wasm_bindgen::prelude::*;
+use super::ai::NaiveAI;
+use super::board::Board;
+use super::board::BoardSize;
+use super::board::Point;
+use super::game::Game;
+use super::protocol;
+use super::ActionType;
+
+// The game for JavaScript. Boards and probabilities come back as JSON strings in
+// the same shape as the server's, so JSON.parse is all the front end needs:
+//   const game = new Game(9, 9, 10);
+//   let view = JSON.parse(game.probe(4, 4));
+//   while (!game.think(10)) { await nextFrame(); }
+//   const risks = JSON.parse(game.probabilities());
+#[wasm_bindgen(js_name = Game)]
+pub struct BrowserGame {
+    game: Game,
+    ai: NaiveAI
+}
+
+#[wasm_bindgen(js_class = Game)]
+impl BrowserGame {
+    // the same seed and first click deal the same board, no seed deals a fresh one
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: usize, height: usize, mines: usize, seed: Option<u32>) -> Result<BrowserGame, JsError> {
+        let board = Board::new_from_size(BoardSize::new(width, height), mines)
+            .map_err(|error| JsError::new(&error.to_string()))?;
+        let board = match seed {
+            Some(seed) => board.with_seed(seed as u64),
+            None => board
+        };
+        // nothing in a browser may block or start a thread, the AI only samples when think is called
+        let ai = NaiveAI::new(0, 1000).with_verbose(false).single_threaded();
+        Ok(BrowserGame{game: Game::new(board), ai})
+    }
+
+    pub fn probe(&mut self, row: usize, column: usize) -> Result<String, JsError> {
+        self.play(ActionType::Click(Point(row, column, 0)))
+    }
+
+    pub fn flag(&mut self, row: usize, column: usize) -> Result<String, JsError> {
+        self.play(ActionType::Flag(Point(row, column, 0)))
+    }
+
+    pub fn chord(&mut self, row: usize, column: usize) -> Result<String, JsError> {
+        self.play(ActionType::Chord(Point(row, column, 0)))
+    }
+
+    // what the player sees with the status, as in GET /games/ID
+    pub fn state(&self) -> String {
+        self.game.view().to_string()
+    }
+
+    // Lets the AI sample for about slice milliseconds, call it from animation frames
+    // or timeouts until it says it's done. Moves start it over
+    pub fn think(&mut self, slice: f64) -> bool {
+        let slice = Duration::from_micros((slice.max(0.0) * 1000.0) as u64);
+        self.ai.think(self.game.board(), slice)
+    }
+
+    // the chance of a mine next to each number from the thinking done so far
+    pub fn probabilities(&self) -> String {
+        protocol::probabilities_json(&self.ai.probabilities_so_far(self.game.board())).to_string()
+    }
+}
+
+impl BrowserGame {
+    fn play(&mut self, action: ActionType) -> Result<String, JsError> {
+        self.game.play(action).map_err(|problem| JsError::new(&problem))?;
+        Ok(self.state())
+    }
+}
+
+// wasm-pack test --node, or any wasm-bindgen-test-runner
+#[cfg(test)]
+mod wasm_tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+    use crate::json::Json;
+
+    #[wasm_bindgen_test]
+    fn play_and_think() {
+        let mut game = BrowserGame::new(9, 9, 10, Some(3)).unwrap();
+        let view = Json::parse(&game.probe(0, 0).unwrap()).unwrap();
+        assert_eq!(view.get("status").and_then(Json::as_str), Some("playing"));
+        assert!(game.probe(10, 10).is_err());
+
+        let mut slices = 0;
+        while !game.think(5.0) {
+            slices += 1;
+        }
+        assert!(slices > 0);
+        match Json::parse(&game.probabilities()).unwrap() {
+            Json::Array(cells) => assert!(!cells.is_empty()),
+            other => panic!("expected a list, got {}", other)
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn seeds_repeat() {
+        let deal = || {
+            let mut game = BrowserGame::new(8, 8, 10, Some(11)).unwrap();
+            game.probe(4, 4).unwrap()
+        };
+        assert_eq!(deal(), deal());
+    }
+}